@@ -0,0 +1,77 @@
+use super::{
+    clash::ClashRules,
+    conflict::ConflictPolicy,
+    filter::{PathFilter, PathPattern},
+    output::OutputFormat,
+    symlink::SymlinkPolicy,
+};
+use regex::Regex;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct CommonArgs {
+    /// Revert the operations recorded in the given journal file
+    #[structopt(long)]
+    pub undo: Option<PathBuf>,
+    /// Revert every completed operation if any of them fails
+    #[structopt(long)]
+    pub atomic: bool,
+    /// How to resolve files that would overwrite each other or existing files
+    #[structopt(long, default_value = "abort", possible_values = ConflictPolicy::VARIANTS)]
+    pub on_conflict: ConflictPolicy,
+    /// Output format of the plan and the results
+    #[structopt(long, default_value = "text", possible_values = OutputFormat::VARIANTS)]
+    pub format: OutputFormat,
+    /// Only operate on paths matching these globs, patterns without a '/' match the file name
+    #[structopt(long)]
+    pub include: Vec<PathPattern>,
+    /// Skip paths matching these globs, patterns without a '/' match the file name
+    #[structopt(long)]
+    pub exclude: Vec<PathPattern>,
+    /// Only operate on paths whose relative path matches all of these regexes
+    #[structopt(long = "match", name = "match")]
+    pub matches: Vec<Regex>,
+    /// Treat names that only differ in case as the same file, e.g. on vfat or exfat drives
+    #[structopt(long)]
+    pub ignore_case: bool,
+    /// Treat names that only differ in Unicode normalization (NFC/NFD) as the same file
+    #[structopt(long)]
+    pub normalize_unicode: bool,
+    /// How to treat symbolic links: skip them, operate on the links or follow linked folders
+    #[structopt(long, default_value = "link", possible_values = SymlinkPolicy::VARIANTS)]
+    pub symlinks: SymlinkPolicy,
+    /// Do not respect .gitignore, .ignore and tool specific ignore files
+    #[structopt(long)]
+    pub no_ignore: bool,
+    /// Number of operations to run in parallel, dependent operations still run in order
+    #[structopt(short, long, default_value = "1")]
+    pub jobs: usize,
+    /// Confirm, skip or edit every operation, the selected ones are only executed together with
+    /// the flag that executes them
+    #[structopt(short, long, conflicts_with = "undo")]
+    pub interactive: bool,
+    /// Edit the destinations of the planned operations in $VISUAL or $EDITOR
+    #[structopt(long, conflicts_with = "undo")]
+    pub edit: bool,
+    /// Refuse to run if some paths could not be scanned
+    #[structopt(long)]
+    pub strict: bool,
+    /// Apply a named profile of options from the config files
+    #[structopt(long)]
+    pub profile: Option<String>,
+}
+
+impl CommonArgs {
+    pub fn path_filter(&self) -> PathFilter {
+        PathFilter::new(
+            self.include.clone(),
+            self.exclude.clone(),
+            self.matches.clone(),
+        )
+    }
+
+    pub fn clash_rules(&self) -> ClashRules {
+        ClashRules::new(self.ignore_case, self.normalize_unicode)
+    }
+}
@@ -0,0 +1,136 @@
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    path::{Component, Path, PathBuf},
+};
+use unicode_normalization::UnicodeNormalization;
+
+/// How names are compared when looking for files that would overwrite each
+/// other, e.g. on case-insensitive mounts or shares that normalize names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ClashRules {
+    fold_case: bool,
+    normalize_unicode: bool,
+}
+
+impl ClashRules {
+    pub fn new(fold_case: bool, normalize_unicode: bool) -> Self {
+        Self {
+            fold_case,
+            normalize_unicode,
+        }
+    }
+
+    /// The path that every path clashing with `path` maps to, names that are
+    /// not valid UTF-8 are compared byte-for-byte.
+    pub fn key(&self, path: &Path) -> PathBuf {
+        if !self.fold_case && !self.normalize_unicode {
+            return path.to_path_buf();
+        }
+        path.components()
+            .map(|component| match component {
+                Component::Normal(name) => match name.to_str() {
+                    Some(name) => OsString::from(self.fold(name)),
+                    None => name.to_os_string(),
+                },
+                component => component.as_os_str().to_os_string(),
+            })
+            .collect()
+    }
+
+    fn fold(&self, name: &str) -> String {
+        // NFC and NFD agree on which names are equivalent, NFD is used as
+        // case folding keeps the decomposed marks intact
+        let name = if self.normalize_unicode {
+            name.nfd().collect::<String>()
+        } else {
+            name.to_string()
+        };
+        if self.fold_case {
+            name.to_lowercase()
+        } else {
+            name
+        }
+    }
+
+    pub fn clash(&self, path: &Path, other: &Path) -> bool {
+        path == other || self.key(path) == self.key(other)
+    }
+
+    pub fn index<'a>(&self, paths: impl IntoIterator<Item = &'a PathBuf>) -> ClashIndex {
+        ClashIndex {
+            rules: *self,
+            keys: paths.into_iter().map(|path| self.key(path)).collect(),
+        }
+    }
+}
+
+/// A set of paths that answers whether a path clashes with any of them.
+#[derive(Debug, Clone)]
+pub struct ClashIndex {
+    rules: ClashRules,
+    keys: HashSet<PathBuf>,
+}
+
+impl ClashIndex {
+    pub fn contains(&self, path: &Path) -> bool {
+        self.keys.contains(&self.rules.key(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NFC: &str = "/work/caf\u{e9}.txt";
+    const NFD: &str = "/work/cafe\u{301}.txt";
+
+    fn clash(rules: ClashRules, path: &str, other: &str) -> bool {
+        rules.clash(Path::new(path), Path::new(other))
+    }
+
+    #[test]
+    fn compares_byte_for_byte_by_default() {
+        let rules = ClashRules::default();
+        assert!(clash(rules, "/work/a.jpg", "/work/a.jpg"));
+        assert!(!clash(rules, "/work/Photo.JPG", "/work/photo.jpg"));
+        assert!(!clash(rules, NFC, NFD));
+    }
+
+    #[test]
+    fn folds_case_and_normalizes_independently() {
+        let case = ClashRules::new(true, false);
+        assert!(clash(case, "/work/Photo.JPG", "/work/photo.jpg"));
+        assert!(clash(case, "/Work/ÄRGER.txt", "/work/ärger.txt"));
+        assert!(!clash(case, NFC, NFD));
+
+        let unicode = ClashRules::new(false, true);
+        assert!(clash(unicode, NFC, NFD));
+        assert!(!clash(unicode, "/work/Photo.JPG", "/work/photo.jpg"));
+
+        let both = ClashRules::new(true, true);
+        assert!(clash(both, "/work/CAFE\u{301}.TXT", NFC));
+        assert!(!clash(both, "/work/cafe.txt", NFC));
+    }
+
+    #[test]
+    fn index_finds_clashing_paths() {
+        let existing = vec![PathBuf::from("/work/Photo.JPG"), PathBuf::from(NFD)];
+        let index = ClashRules::new(true, true).index(&existing);
+        assert!(index.contains(Path::new("/work/photo.jpg")));
+        assert!(index.contains(Path::new(NFC)));
+        assert!(!index.contains(Path::new("/work/photo.png")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_names_that_are_not_utf8() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+        let rules = ClashRules::new(true, true);
+        let name = Path::new("/WORK").join(OsStr::from_bytes(b"A\xff"));
+        assert_eq!(
+            rules.key(&name),
+            Path::new("/work").join(OsStr::from_bytes(b"A\xff"))
+        );
+    }
+}
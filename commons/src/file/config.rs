@@ -0,0 +1,440 @@
+//! Layered defaults for the command line arguments of the file tools.
+//!
+//! Options are looked up from the following sources, later ones override
+//! earlier ones:
+//!
+//! 1. the top level of `~/.config/small_utils/config.toml`
+//! 2. its `[<tool>]` section, e.g. `[denest]`
+//! 3. the top level of `.small_utils.toml` in the working directory
+//! 4. its `[<tool>]` section
+//! 5. the `[profiles.<name>]` table selected with `--profile`, the
+//!    directory file's profile overriding the user file's one
+//! 6. `SMALL_UTILS_<OPTION>` environment variables, the values of list options
+//!    separated by ','
+//! 7. flags given on the command line
+//!
+//! Keys are the long flag names, e.g. `on-conflict = "suffix"` or
+//! `extensions = ["mkv", "mp4"]`. Keys outside of tool sections that a tool
+//! does not know are ignored, so shared defaults can be kept at the top level.
+//! The flags that execute, undo or pick the working directory are only taken
+//! from the command line. The options that send files elsewhere or let them be
+//! overwritten are not taken from the directory config. `--no-<option>` on the command line drops the value
+//! the other sources give to an option, e.g. `--no-cleanup`.
+
+use super::errors::ConfigError;
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::OsString,
+    fs, io, mem,
+    path::{Path, PathBuf},
+};
+use structopt::{
+    clap::{App, ArgMatches, ErrorKind},
+    StructOpt,
+};
+use toml::{Table, Value};
+
+pub const CONFIG_DIR: &str = "small_utils";
+pub const CONFIG_FILE: &str = "config.toml";
+pub const DIRECTORY_CONFIG_FILE: &str = ".small_utils.toml";
+pub const ENV_PREFIX: &str = "SMALL_UTILS_";
+const PROFILES: &str = "profiles";
+const PROFILE_ARG: &str = "profile";
+const WORKING_DIR_ARG: &str = "working-dir";
+const NEGATION_PREFIX: &str = "--no-";
+// a config file in a downloaded folder must not be able to start or redirect a run
+const COMMAND_LINE_ONLY: &[&str] = &[
+    PROFILE_ARG,
+    "do-moves",
+    "do-renames",
+    "undo",
+    WORKING_DIR_ARG,
+];
+// nor move files out of the folder, follow links out of it or overwrite files
+const NOT_FROM_DIRECTORY: &[&str] = &["include-root", "on-conflict", "symlinks", "target"];
+// the options taking several values, environment variables separate them by ','
+const LIST_OPTIONS: &[&str] = &["exclude", "extensions", "folders", "include", "match"];
+
+pub fn user_config_path() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join(CONFIG_DIR).join(CONFIG_FILE))
+}
+
+pub fn load_table(path: &Path) -> Result<Option<Table>, ConfigError> {
+    match fs::read_to_string(path) {
+        Ok(contents) => contents
+            .parse::<Table>()
+            .map(Some)
+            .map_err(|e| ConfigError::Malformed(path.to_path_buf(), e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(ConfigError::Io(path.to_path_buf(), e)),
+    }
+}
+
+// drops the options a directory config must not set, from its sections and profiles too
+fn directory_table(mut table: Table) -> Table {
+    table.retain(|key, value| {
+        if let Value::Table(section) = value {
+            *section = directory_table(mem::take(section));
+        }
+        !NOT_FROM_DIRECTORY.contains(&key)
+    });
+    table
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Layers {
+    values: BTreeMap<String, Value>,
+}
+
+impl Layers {
+    fn set(&mut self, key: &str, value: Value) {
+        if !COMMAND_LINE_ONLY.contains(&key) {
+            self.values.insert(key.to_string(), value);
+        }
+    }
+
+    fn merge_table(&mut self, table: &Table) {
+        table
+            .iter()
+            .filter(|(_, value)| !value.is_table())
+            .for_each(|(key, value)| self.set(key, value.clone()));
+    }
+
+    pub fn merge_file(&mut self, table: &Table, tool: &str) {
+        self.merge_table(table);
+        if let Some(Value::Table(section)) = table.get(tool) {
+            self.merge_table(section);
+        }
+    }
+
+    pub fn merge_profile(&mut self, tables: &[&Table], tool: &str, name: &str) -> bool {
+        let mut found = false;
+        for table in tables {
+            let profile = table
+                .get(PROFILES)
+                .and_then(Value::as_table)
+                .and_then(|profiles| profiles.get(name))
+                .and_then(Value::as_table);
+            if let Some(profile) = profile {
+                self.merge_file(profile, tool);
+                found = true;
+            }
+        }
+        found
+    }
+
+    pub fn merge_env(&mut self, vars: impl IntoIterator<Item = (String, String)>) {
+        for (name, value) in vars {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let key = key.to_lowercase().replace('_', "-");
+            let value = if LIST_OPTIONS.contains(&key.as_str()) {
+                Value::Array(
+                    value
+                        .split(',')
+                        .map(|value| Value::String(value.trim().to_string()))
+                        .collect(),
+                )
+            } else {
+                Value::String(value)
+            };
+            self.set(&key, value);
+        }
+    }
+
+    pub fn to_args(&self, accepts: impl Fn(&str) -> bool) -> Result<Vec<OsString>, ConfigError> {
+        let mut args = vec![];
+        for (key, value) in self.values.iter().filter(|(key, _)| accepts(key)) {
+            let flag = OsString::from(format!("--{key}"));
+            match value {
+                Value::Boolean(true) => args.push(flag),
+                Value::Boolean(false) => {}
+                Value::String(value) if matches!(value.as_str(), "true" | "false") => {
+                    if value == "true" {
+                        args.push(flag);
+                    }
+                }
+                Value::Array(values) => {
+                    for value in values {
+                        args.push(flag.clone());
+                        args.push(scalar(key, value)?);
+                    }
+                }
+                value => {
+                    args.push(flag);
+                    args.push(scalar(key, value)?);
+                }
+            }
+        }
+        Ok(args)
+    }
+}
+
+fn scalar(key: &str, value: &Value) -> Result<OsString, ConfigError> {
+    match value {
+        Value::String(value) => Ok(value.into()),
+        Value::Integer(value) => Ok(value.to_string().into()),
+        Value::Float(value) => Ok(value.to_string().into()),
+        _ => Err(ConfigError::UnsupportedValue(key.to_string())),
+    }
+}
+
+fn accepts_flag(app: &App, key: &str) -> bool {
+    let probe = [app.get_name().to_string(), format!("--{key}")];
+    !matches!(
+        app.clone().get_matches_from_safe(probe),
+        Err(e) if e.kind == ErrorKind::UnknownArgument
+    )
+}
+
+// structopt names the arguments after their long flags, so keys are arg names as they are
+fn given_on_command_line(matches: &ArgMatches, key: &str) -> bool {
+    matches.occurrences_of(key) > 0
+}
+
+// takes the `--no-<option>` flags the tool does not define itself out of `argv`
+fn split_negations(argv: Vec<OsString>, app: &App) -> (Vec<OsString>, Vec<String>) {
+    let mut negated = vec![];
+    let argv = argv
+        .into_iter()
+        .filter(|arg| {
+            let key = arg
+                .to_str()
+                .and_then(|arg| arg.strip_prefix(NEGATION_PREFIX));
+            match key {
+                Some(key) if accepts_flag(app, key) && !accepts_flag(app, &format!("no-{key}")) => {
+                    negated.push(key.to_string());
+                    false
+                }
+                _ => true,
+            }
+        })
+        .collect();
+    (argv, negated)
+}
+
+pub fn layered_args(argv: Vec<OsString>, app: &App) -> Result<Vec<OsString>, ConfigError> {
+    let (argv, negated) = split_negations(argv, app);
+    let matches = match app.clone().get_matches_from_safe(&argv) {
+        Ok(matches) => matches,
+        // let the real parse report invalid command lines
+        Err(_) => return Ok(argv),
+    };
+    if matches.is_present("undo") {
+        return Ok(argv);
+    }
+    let tool = app.get_name().to_string();
+    let working_dir = match matches.value_of_os(WORKING_DIR_ARG) {
+        Some(dir) => PathBuf::from(dir),
+        None => env::current_dir().map_err(|e| ConfigError::Io(PathBuf::from("."), e))?,
+    };
+
+    let user = match user_config_path() {
+        Some(path) => load_table(&path)?,
+        None => None,
+    };
+    let directory = load_table(&working_dir.join(DIRECTORY_CONFIG_FILE))?.map(directory_table);
+    let tables = [&user, &directory]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+    let mut layers = Layers::default();
+    tables
+        .iter()
+        .for_each(|table| layers.merge_file(table, &tool));
+    let profile = matches
+        .value_of(PROFILE_ARG)
+        .map(str::to_string)
+        .or_else(|| env::var(format!("{ENV_PREFIX}PROFILE")).ok());
+    if let Some(profile) = profile {
+        if !layers.merge_profile(&tables, &tool, &profile) {
+            return Err(ConfigError::UnknownProfile(profile));
+        }
+    }
+    layers.merge_env(env::vars());
+
+    let extra = layers.to_args(|key| {
+        !negated.iter().any(|negated| negated == key)
+            && !given_on_command_line(&matches, key)
+            && accepts_flag(app, key)
+    })?;
+    Ok(argv.into_iter().chain(extra).collect())
+}
+
+pub fn from_layered_args<A: StructOpt>() -> Result<A, ConfigError> {
+    let argv = layered_args(env::args_os().collect(), &A::clap())?;
+    Ok(A::from_iter(argv))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::clap::Arg;
+
+    fn table(contents: &str) -> Table {
+        contents.parse().unwrap()
+    }
+
+    fn args(layers: &Layers) -> Vec<String> {
+        layers
+            .to_args(|key| key != "unknown")
+            .unwrap()
+            .into_iter()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn later_layers_override_earlier_ones() {
+        let user = table(
+            r#"
+            on-conflict = "skip"
+            unknown = 1
+            [denest]
+            extensions = ["mkv", "mp4"]
+            depth = 3
+            [profiles.photos]
+            extensions = ["jpg"]
+            "#,
+        );
+        let directory = table(
+            r#"
+            [denest]
+            depth = 2
+            cleanup = true
+            [rempref]
+            recursive = true
+            "#,
+        );
+        let mut layers = Layers::default();
+        layers.merge_file(&user, "denest");
+        layers.merge_file(&directory, "denest");
+        assert!(layers.merge_profile(&[&user, &directory], "denest", "photos"));
+        assert!(!layers.clone().merge_profile(&[&user], "denest", "videos"));
+        layers.merge_env([
+            ("SMALL_UTILS_ON_CONFLICT".to_string(), "suffix".to_string()),
+            (
+                "SMALL_UTILS_EXCLUDE".to_string(),
+                "*.tmp, *.part".to_string(),
+            ),
+            ("SMALL_UTILS_SEPARATOR".to_string(), ", ".to_string()),
+            ("OTHER".to_string(), "value".to_string()),
+        ]);
+        assert_eq!(
+            args(&layers),
+            vec![
+                "--cleanup",
+                "--depth",
+                "2",
+                "--exclude",
+                "*.tmp",
+                "--exclude",
+                "*.part",
+                "--extensions",
+                "jpg",
+                "--on-conflict",
+                "suffix",
+                "--separator",
+                ", ",
+            ]
+        );
+    }
+
+    #[test]
+    fn directory_configs_cannot_send_files_elsewhere() {
+        let contents = r#"
+            target = "/elsewhere"
+            on-conflict = "overwrite"
+            cleanup = true
+            [denest]
+            include-root = true
+            depth = 2
+            [profiles.photos.denest]
+            symlinks = "follow"
+            target = "/elsewhere"
+            extensions = ["jpg"]
+            "#;
+        let directory = directory_table(table(contents));
+        let mut layers = Layers::default();
+        layers.merge_file(&directory, "denest");
+        assert!(layers.merge_profile(&[&directory], "denest", "photos"));
+        assert_eq!(
+            args(&layers),
+            vec!["--cleanup", "--depth", "2", "--extensions", "jpg"]
+        );
+
+        let user = table(contents);
+        let mut layers = Layers::default();
+        layers.merge_file(&user, "denest");
+        assert!(args(&layers).contains(&"--target".to_string()));
+    }
+
+    #[test]
+    fn execution_flags_only_come_from_the_command_line() {
+        let file = table(
+            r#"
+            do-moves = true
+            do-renames = true
+            undo = "journal.json"
+            working-dir = "/"
+            profile = "photos"
+            cleanup = true
+            "#,
+        );
+        let mut layers = Layers::default();
+        layers.merge_file(&file, "denest");
+        layers.merge_env([
+            ("SMALL_UTILS_DO_MOVES".to_string(), "true".to_string()),
+            ("SMALL_UTILS_WORKING_DIR".to_string(), "/".to_string()),
+        ]);
+        assert_eq!(args(&layers), vec!["--cleanup"]);
+    }
+
+    #[test]
+    fn negations_are_taken_out_of_the_command_line() {
+        let app = App::new("tool")
+            .arg(Arg::with_name("cleanup").long("cleanup"))
+            .arg(Arg::with_name("depth").long("depth").takes_value(true))
+            .arg(Arg::with_name("no_ignore").long("no-ignore"));
+        let argv = [
+            "tool",
+            "--no-cleanup",
+            "--no-depth",
+            "--no-ignore",
+            "--no-such",
+        ]
+        .into_iter()
+        .map(OsString::from)
+        .collect();
+        let (argv, negated) = split_negations(argv, &app);
+        assert_eq!(argv, vec!["tool", "--no-ignore", "--no-such"]);
+        assert_eq!(negated, vec!["cleanup", "depth"]);
+    }
+
+    #[test]
+    fn finds_the_flags_given_on_the_command_line() {
+        #[derive(StructOpt)]
+        #[allow(dead_code)]
+        struct Args {
+            #[structopt(long)]
+            on_conflict: Option<String>,
+            #[structopt(long)]
+            working_dir: Option<PathBuf>,
+        }
+        let matches = Args::clap().get_matches_from(["tool", "--on-conflict", "suffix"]);
+        assert!(given_on_command_line(&matches, "on-conflict"));
+        assert!(!given_on_command_line(&matches, "working-dir"));
+        let matches = Args::clap().get_matches_from(["tool", "--working-dir", "/tmp"]);
+        assert_eq!(
+            matches.value_of_os(WORKING_DIR_ARG),
+            Some(OsString::from("/tmp").as_os_str())
+        );
+    }
+}
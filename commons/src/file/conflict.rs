@@ -0,0 +1,434 @@
+use super::{
+    clash::ClashRules,
+    filesystem::FileSystem,
+    model::{FailedFileOperation, FileOperationTask, Resolution},
+    ordering::vacated_paths,
+    traits::ToFailed,
+    walkdir,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::SystemTime,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    #[default]
+    Abort,
+    Skip,
+    Suffix,
+    Overwrite,
+    KeepNewer,
+    KeepLarger,
+}
+
+impl ConflictPolicy {
+    pub const VARIANTS: &'static [&'static str] = &[
+        "abort",
+        "skip",
+        "suffix",
+        "overwrite",
+        "keep-newer",
+        "keep-larger",
+    ];
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "abort" => Ok(Self::Abort),
+            "skip" => Ok(Self::Skip),
+            "suffix" => Ok(Self::Suffix),
+            "overwrite" => Ok(Self::Overwrite),
+            "keep-newer" => Ok(Self::KeepNewer),
+            "keep-larger" => Ok(Self::KeepLarger),
+            _ => Err(format!("unknown conflict policy '{s}'")),
+        }
+    }
+}
+
+impl Display for ConflictPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Abort => "abort",
+            Self::Skip => "skip",
+            Self::Suffix => "suffix",
+            Self::Overwrite => "overwrite",
+            Self::KeepNewer => "keep-newer",
+            Self::KeepLarger => "keep-larger",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Rank {
+    Modified(SystemTime),
+    Size(u64),
+}
+
+fn rank(fs: &dyn FileSystem, path: &Path, policy: ConflictPolicy) -> Option<Rank> {
+    let metadata = fs.metadata(path).ok()?;
+    match policy {
+        ConflictPolicy::KeepNewer => Some(Rank::Modified(metadata.modified)),
+        _ => Some(Rank::Size(metadata.len)),
+    }
+}
+
+// finds the files on disk that paths clash with, e.g. `Photo.JPG` for `photo.jpg` when case is folded
+struct ExistingFiles<'a> {
+    fs: &'a dyn FileSystem,
+    rules: ClashRules,
+    dirs: HashMap<PathBuf, HashMap<PathBuf, PathBuf>>,
+}
+
+impl<'a> ExistingFiles<'a> {
+    fn new(fs: &'a dyn FileSystem, rules: ClashRules) -> Self {
+        Self {
+            fs,
+            rules,
+            dirs: HashMap::new(),
+        }
+    }
+
+    fn find(&mut self, path: &Path) -> Option<PathBuf> {
+        if self.fs.exists(path) {
+            return Some(path.to_path_buf());
+        }
+        if self.rules == ClashRules::default() {
+            return None;
+        }
+        let dir = path.parent()?;
+        let (fs, rules) = (self.fs, self.rules);
+        self.dirs
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| {
+                walkdir(fs, dir, Some(1))
+                    .into_iter()
+                    .map(|entry| (rules.key(&entry), entry))
+                    .collect()
+            })
+            .get(&rules.key(path))
+            .cloned()
+    }
+}
+
+fn suffixed_path(existing: &mut ExistingFiles, path: &Path, taken: &HashSet<PathBuf>) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    let mut i = 1;
+    loop {
+        let candidate = path.with_file_name(format!("{stem} ({i}){extension}"));
+        if !taken.contains(&existing.rules.key(&candidate)) && existing.find(&candidate).is_none() {
+            return candidate;
+        }
+        i += 1;
+    }
+}
+
+/// Applies `policy` to the tasks that would overwrite each other or existing
+/// files, names are compared with `rules`.
+pub fn resolve_conflicts(
+    fs: &dyn FileSystem,
+    mut tasks: Vec<FileOperationTask>,
+    policy: ConflictPolicy,
+    rules: ClashRules,
+) -> (Vec<FileOperationTask>, Vec<FailedFileOperation>) {
+    let mut skipped = vec![];
+    if policy == ConflictPolicy::Abort {
+        return (tasks, skipped);
+    }
+    // skipping a task can turn a task that relied on it into a conflict, so repeat until stable
+    loop {
+        let (kept, newly_skipped) = resolve_once(fs, tasks, policy, rules);
+        tasks = kept;
+        if newly_skipped.is_empty() {
+            break;
+        }
+        skipped.extend(newly_skipped);
+    }
+    skipped.sort();
+    (tasks, skipped)
+}
+
+fn resolve_once(
+    fs: &dyn FileSystem,
+    mut tasks: Vec<FileOperationTask>,
+    policy: ConflictPolicy,
+    rules: ClashRules,
+) -> (Vec<FileOperationTask>, Vec<FailedFileOperation>) {
+    let vacated = rules.index(&vacated_paths(&tasks));
+    let mut existing_files = ExistingFiles::new(fs, rules);
+    let mut taken = tasks
+        .iter()
+        .map(|task| rules.key(&task.to))
+        .collect::<HashSet<_>>();
+    let mut groups: Vec<(PathBuf, Vec<usize>)> = vec![];
+    let mut group_of: HashMap<PathBuf, usize> = HashMap::new();
+    for (i, task) in tasks.iter().enumerate() {
+        let key = rules.key(&task.to);
+        match group_of.get(&key) {
+            Some(&group) => groups[group].1.push(i),
+            None => {
+                group_of.insert(key, groups.len());
+                groups.push((task.to.clone(), vec![i]));
+            }
+        }
+    }
+
+    let mut skip = HashMap::new();
+    for (target, members) in groups {
+        let existing = existing_files
+            .find(&target)
+            .filter(|existing| !vacated.contains(existing));
+        let exists = existing.is_some();
+        if members.len() == 1 && !exists {
+            continue;
+        }
+        match policy {
+            ConflictPolicy::Abort => {}
+            ConflictPolicy::Skip => {
+                let reason = if exists {
+                    "skipped, would overwrite an existing file"
+                } else {
+                    "skipped, other files would be moved to the same place"
+                };
+                members.iter().for_each(|&i| {
+                    skip.insert(i, reason.to_string());
+                });
+            }
+            ConflictPolicy::Suffix => {
+                let renamed = if exists { &members[..] } else { &members[1..] };
+                for &i in renamed {
+                    let to = suffixed_path(&mut existing_files, &tasks[i].to, &taken);
+                    taken.insert(rules.key(&to));
+                    tasks[i].to = to;
+                    tasks[i].resolution = Some(Resolution::Suffixed);
+                }
+            }
+            ConflictPolicy::Overwrite => {
+                members.iter().for_each(|&i| {
+                    tasks[i].resolution = Some(Resolution::Overwrites);
+                });
+            }
+            ConflictPolicy::KeepNewer | ConflictPolicy::KeepLarger => {
+                let quality = if policy == ConflictPolicy::KeepNewer {
+                    "newer"
+                } else {
+                    "larger"
+                };
+                let winner = members
+                    .iter()
+                    .copied()
+                    .max_by_key(|&i| rank(fs, &tasks[i].from, policy))
+                    .unwrap_or(members[0]);
+                let existing_wins = existing.as_ref().is_some_and(|existing| {
+                    rank(fs, existing, policy) >= rank(fs, &tasks[winner].from, policy)
+                });
+                for &i in &members {
+                    if existing_wins {
+                        skip.insert(i, format!("skipped, the existing file is {quality}"));
+                    } else if i != winner {
+                        skip.insert(
+                            i,
+                            format!("skipped, a {quality} file is moved to the same place"),
+                        );
+                    } else if exists {
+                        tasks[i].resolution = Some(Resolution::Overwrites);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut kept = vec![];
+    let mut skipped = vec![];
+    for (i, task) in tasks.into_iter().enumerate() {
+        match skip.get(&i) {
+            Some(reason) => skipped.push(task.to_failed(reason)),
+            None => kept.push(task),
+        }
+    }
+    (kept, skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::filesystem::MemoryFs;
+    use std::time::Duration;
+
+    fn task(from: &str, to: &str) -> FileOperationTask {
+        FileOperationTask::new(PathBuf::from(from), PathBuf::from(to))
+    }
+
+    fn skipped(skipped: &[FailedFileOperation]) -> Vec<(&str, &str)> {
+        skipped
+            .iter()
+            .map(|failed| (failed.file_path.to_str().unwrap(), failed.reason.as_str()))
+            .collect()
+    }
+
+    fn at(seconds: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn skip_repeats_until_no_conflict_is_left() {
+        let fs = MemoryFs::new();
+        fs.add_file("/w/b", "");
+        fs.add_file("/w/d/b", "");
+        fs.add_file("/w/e/c", "");
+        let tasks = vec![
+            task("/w/b", "/w/c"),
+            task("/w/d/b", "/w/b"),
+            task("/w/e/c", "/w/c"),
+        ];
+        let (kept, skipped_tasks) =
+            resolve_conflicts(&fs, tasks, ConflictPolicy::Skip, ClashRules::default());
+        assert!(kept.is_empty());
+        // /w/d/b only conflicts once /w/b is no longer moved away
+        assert_eq!(
+            skipped(&skipped_tasks),
+            vec![
+                (
+                    "/w/b",
+                    "skipped, other files would be moved to the same place"
+                ),
+                ("/w/d/b", "skipped, would overwrite an existing file"),
+                (
+                    "/w/e/c",
+                    "skipped, other files would be moved to the same place"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn overwrite_marks_every_conflicting_task() {
+        let fs = MemoryFs::new();
+        fs.add_file("/w/x", "");
+        let tasks = vec![task("/w/a/x", "/w/x"), task("/w/a/y", "/w/y")];
+        let (kept, skipped_tasks) =
+            resolve_conflicts(&fs, tasks, ConflictPolicy::Overwrite, ClashRules::default());
+        assert!(skipped_tasks.is_empty());
+        assert_eq!(kept[0].resolution, Some(Resolution::Overwrites));
+        assert_eq!(kept[1].resolution, None);
+    }
+
+    #[test]
+    fn keep_newer_moves_only_the_newest_file() {
+        let fs = MemoryFs::new();
+        fs.add_file("/w/x", "");
+        fs.add_file("/w/a/x", "");
+        fs.add_file("/w/b/x", "");
+        fs.set_modified("/w/x", at(10));
+        fs.set_modified("/w/a/x", at(30));
+        fs.set_modified("/w/b/x", at(20));
+        let tasks = vec![task("/w/a/x", "/w/x"), task("/w/b/x", "/w/x")];
+        let (kept, skipped_tasks) =
+            resolve_conflicts(&fs, tasks, ConflictPolicy::KeepNewer, ClashRules::default());
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].from, PathBuf::from("/w/a/x"));
+        assert_eq!(kept[0].resolution, Some(Resolution::Overwrites));
+        assert_eq!(
+            skipped(&skipped_tasks),
+            vec![("/w/b/x", "skipped, a newer file is moved to the same place")]
+        );
+    }
+
+    #[test]
+    fn keep_newer_keeps_a_newer_existing_file() {
+        let fs = MemoryFs::new();
+        fs.add_file("/w/x", "");
+        fs.add_file("/w/a/x", "");
+        fs.set_modified("/w/x", at(30));
+        fs.set_modified("/w/a/x", at(20));
+        let tasks = vec![task("/w/a/x", "/w/x")];
+        let (kept, skipped_tasks) =
+            resolve_conflicts(&fs, tasks, ConflictPolicy::KeepNewer, ClashRules::default());
+        assert!(kept.is_empty());
+        assert_eq!(
+            skipped(&skipped_tasks),
+            vec![("/w/a/x", "skipped, the existing file is newer")]
+        );
+    }
+
+    #[test]
+    fn keep_larger_compares_sizes() {
+        let fs = MemoryFs::new();
+        fs.add_file("/w/x", "existing");
+        fs.add_file("/w/a/x", "small");
+        fs.add_file("/w/a/y", "larger one");
+        fs.add_file("/w/b/y", "small");
+        let tasks = vec![
+            task("/w/a/x", "/w/x"),
+            task("/w/a/y", "/w/y"),
+            task("/w/b/y", "/w/y"),
+        ];
+        let (kept, skipped_tasks) = resolve_conflicts(
+            &fs,
+            tasks,
+            ConflictPolicy::KeepLarger,
+            ClashRules::default(),
+        );
+        assert_eq!(kept, vec![task("/w/a/y", "/w/y")]);
+        assert_eq!(
+            skipped(&skipped_tasks),
+            vec![
+                ("/w/a/x", "skipped, the existing file is larger"),
+                (
+                    "/w/b/y",
+                    "skipped, a larger file is moved to the same place"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn compares_names_with_the_clash_rules() {
+        let fs = MemoryFs::new();
+        fs.add_file("/w/1_Photo.JPG", "");
+        fs.add_file("/w/2_photo.jpg", "");
+        fs.add_file("/w/3_Notes.txt", "");
+        fs.add_file("/w/notes.TXT", "");
+        let tasks = vec![
+            task("/w/1_Photo.JPG", "/w/Photo.JPG"),
+            task("/w/2_photo.jpg", "/w/photo.jpg"),
+            task("/w/3_Notes.txt", "/w/Notes.txt"),
+        ];
+        let (kept, _) = resolve_conflicts(
+            &fs,
+            tasks.clone(),
+            ConflictPolicy::Suffix,
+            ClashRules::default(),
+        );
+        assert_eq!(kept, tasks);
+
+        let (kept, _) = resolve_conflicts(
+            &fs,
+            tasks,
+            ConflictPolicy::Suffix,
+            ClashRules::new(true, false),
+        );
+        let destinations = kept
+            .iter()
+            .map(|task| task.to.to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            destinations,
+            vec!["/w/Photo.JPG", "/w/photo (1).jpg", "/w/Notes (1).txt"]
+        );
+    }
+}
@@ -0,0 +1,202 @@
+use super::{errors::PlanEditError, model::FileOperationTask, traits::Relativize};
+use std::{
+    collections::{BTreeSet, HashMap},
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{self, Command},
+};
+
+const HEADER: &str = "\
+# Edit the destinations below, every line is '<number><TAB><destination>'.
+# Lines starting with '#' are ignored, the numbers must not be changed or removed.
+";
+
+pub fn render_plan(tasks: &[FileOperationTask], working_dir: &Path) -> String {
+    let mut contents = HEADER.to_string();
+    for (i, task) in tasks.iter().enumerate() {
+        // an absolute destination joins back onto the working dir unchanged
+        let task = task
+            .relativize(working_dir)
+            .unwrap_or_else(|_| task.clone());
+        contents.push_str(&format!(
+            "# {}\n{}\t{}\n",
+            task.from.display(),
+            i + 1,
+            task.to.display()
+        ));
+    }
+    contents
+}
+
+pub fn parse_plan(
+    tasks: &[FileOperationTask],
+    working_dir: &Path,
+    contents: &str,
+) -> Result<Vec<FileOperationTask>, PlanEditError> {
+    let mut destinations = HashMap::new();
+    for (line_number, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let malformed = || PlanEditError::Malformed(line_number + 1, line.to_string());
+        let (number, destination) = line.split_once('\t').ok_or_else(malformed)?;
+        let number = number.trim().parse::<usize>().map_err(|_| malformed())?;
+        let destination = destination.trim();
+        if number == 0 || number > tasks.len() || destination.is_empty() {
+            return Err(malformed());
+        }
+        if destinations
+            .insert(number - 1, working_dir.join(destination))
+            .is_some()
+        {
+            return Err(PlanEditError::DuplicateLine(number));
+        }
+    }
+
+    let missing = (1..=tasks.len())
+        .filter(|number| !destinations.contains_key(&(number - 1)))
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        return Err(PlanEditError::MissingLines(missing));
+    }
+
+    let mut targets = BTreeSet::new();
+    let mut edited = vec![];
+    for (i, task) in tasks.iter().enumerate() {
+        let to = destinations
+            .remove(&i)
+            .expect("every line should be present");
+        if !targets.insert(to.clone()) {
+            return Err(PlanEditError::DuplicateTarget(to));
+        }
+        if to == task.to {
+            edited.push(task.clone());
+        } else {
+            edited.push(task.redirected(to));
+        }
+    }
+    Ok(edited)
+}
+
+fn editor() -> String {
+    env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string())
+}
+
+fn open_editor(path: &Path) -> Result<(), PlanEditError> {
+    let editor = editor();
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+    let status = Command::new(program).args(words).arg(path).status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(PlanEditError::EditorFailed(editor))
+    }
+}
+
+// the temp dir is shared, so the file must be a new one nobody else can read or swap out
+fn create_plan_file(tool: &str) -> io::Result<(PathBuf, File)> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut attempt = 0;
+    loop {
+        let path = env::temp_dir().join(format!("{tool}-plan-{}-{attempt}.txt", process::id()));
+        match options.open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempt < 100 => attempt += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+pub fn edit_plan(
+    tool: &str,
+    tasks: &[FileOperationTask],
+    working_dir: &Path,
+) -> Result<Vec<FileOperationTask>, PlanEditError> {
+    let (path, mut file) = create_plan_file(tool)?;
+    let written = file.write_all(render_plan(tasks, working_dir).as_bytes());
+    drop(file);
+    if let Err(e) = written {
+        let _ = fs::remove_file(&path);
+        return Err(e.into());
+    }
+    let edited = open_editor(&path).and_then(|_| Ok(fs::read_to_string(&path)?));
+    let _ = fs::remove_file(&path);
+    parse_plan(tasks, working_dir, &edited?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn task(from: &str, to: &str) -> FileOperationTask {
+        FileOperationTask::new(PathBuf::from(from), PathBuf::from(to))
+    }
+
+    fn tasks() -> Vec<FileOperationTask> {
+        vec![
+            task("/work/01_a.txt", "/work/a.txt"),
+            task("/work/02_b.txt", "/work/b.txt"),
+        ]
+    }
+
+    #[test]
+    fn reads_back_edited_destinations() {
+        let working_dir = Path::new("/work");
+        let contents = render_plan(&tasks(), working_dir).replace("2\tb.txt", "2\tdir/bee.txt");
+        assert_eq!(
+            parse_plan(&tasks(), working_dir, &contents).unwrap(),
+            vec![
+                task("/work/01_a.txt", "/work/a.txt"),
+                task("/work/02_b.txt", "/work/dir/bee.txt"),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_missing_duplicate_and_malformed_lines() {
+        let working_dir = Path::new("/work");
+        let parse = |contents: &str| parse_plan(&tasks(), working_dir, contents);
+        assert!(matches!(
+            parse("1\ta.txt\n"),
+            Err(PlanEditError::MissingLines(lines)) if lines == vec![2]
+        ));
+        assert!(matches!(
+            parse("1\ta.txt\n1\tc.txt\n2\tb.txt\n"),
+            Err(PlanEditError::DuplicateLine(1))
+        ));
+        assert!(matches!(
+            parse("1\tsame.txt\n2\tsame.txt\n"),
+            Err(PlanEditError::DuplicateTarget(_))
+        ));
+        assert!(matches!(
+            parse("1 a.txt\n2\tb.txt\n"),
+            Err(PlanEditError::Malformed(1, _))
+        ));
+    }
+
+    #[test]
+    fn creates_a_new_private_plan_file_every_time() {
+        let (first, _) = create_plan_file("editor-test").unwrap();
+        let (second, _) = create_plan_file("editor-test").unwrap();
+        assert_ne!(first, second);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&first).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let _ = fs::remove_file(first);
+        let _ = fs::remove_file(second);
+    }
+}
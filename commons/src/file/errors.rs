@@ -1,8 +1,332 @@
-use super::model::FailedFileOperation;
+use super::{executor::Execution, model::FailedFileOperation};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
 use thiserror::Error;
 
+#[derive(Debug, Error)]
+pub enum PathError {
+    #[error("permission denied for {}", .0.display())]
+    PermissionDenied(PathBuf),
+    #[error("{} is not relative to {}", .path.display(), .base.display())]
+    NotRelativizable { path: PathBuf, base: PathBuf },
+    #[error("the name of {} is not valid UTF-8", .0.display())]
+    InvalidUtf8(PathBuf),
+    #[error("{} could not be scanned: {message}", .path.display())]
+    Walk { path: PathBuf, message: String },
+    #[error("{} could not be accessed: {source}", .path.display())]
+    Io { path: PathBuf, source: io::Error },
+}
+
+impl PathError {
+    pub fn from_io(path: &Path, source: io::Error) -> Self {
+        match source.kind() {
+            io::ErrorKind::PermissionDenied => PathError::PermissionDenied(path.to_path_buf()),
+            _ => PathError::Io {
+                path: path.to_path_buf(),
+                source,
+            },
+        }
+    }
+
+    /// Errors without a path of their own, e.g. a failing root, are
+    /// attributed to the `root` of the walk.
+    pub fn from_walk(e: walkdir::Error, root: &Path) -> Self {
+        let path = e.path().unwrap_or(root).to_path_buf();
+        match e.io_error() {
+            Some(source) if source.kind() == io::ErrorKind::PermissionDenied => {
+                PathError::PermissionDenied(path)
+            }
+            Some(source) => PathError::Walk {
+                path,
+                message: source.to_string(),
+            },
+            None => PathError::Walk {
+                path,
+                message: e.to_string(),
+            },
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        match self {
+            PathError::PermissionDenied(path)
+            | PathError::InvalidUtf8(path)
+            | PathError::NotRelativizable { path, .. }
+            | PathError::Walk { path, .. }
+            | PathError::Io { path, .. } => path,
+        }
+    }
+
+    /// The error without its path, for reports that list the path anyway.
+    pub fn reason(&self) -> String {
+        match self {
+            PathError::PermissionDenied(_) => "permission denied".to_string(),
+            PathError::NotRelativizable { base, .. } => {
+                format!("is not relative to {}", base.display())
+            }
+            PathError::InvalidUtf8(_) => "name is not valid UTF-8".to_string(),
+            PathError::Walk { message, .. } => message.clone(),
+            PathError::Io { source, .. } => source.to_string(),
+        }
+    }
+}
+
+/// A folder moved across devices that was copied completely, but whose
+/// source could only be removed in part, so both paths hold files now.
+#[derive(Debug, Error)]
+#[error("{} was copied to {}, but could not be removed afterwards: {source}", .from.display(), .to.display())]
+pub struct PartialMove {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    pub source: io::Error,
+}
+
+impl PartialMove {
+    pub fn into_io(self) -> io::Error {
+        io::Error::new(self.source.kind(), self)
+    }
+
+    /// The partial move `error` was made from, if it was.
+    pub fn of(error: &io::Error) -> Option<&Self> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+/// Why the checks refuse to run an operation, by what would be overwritten.
 #[derive(Debug, Error)]
 pub enum CheckBeforeError {
-    #[error("Some files would be overwritten")]
-    FilesWouldOverwrite(Vec<FailedFileOperation>),
+    #[error("Some files would overwrite each other")]
+    Clash(Vec<FailedFileOperation>),
+    #[error("Some files would overwrite existing files")]
+    Target(Vec<FailedFileOperation>),
+    #[error("Some files would replace existing symlinks")]
+    Symlink(Vec<FailedFileOperation>),
+}
+
+impl CheckBeforeError {
+    /// The error of the first cause any files fail for, clashes between the
+    /// planned files before existing targets and symlinks, the files of the
+    /// other causes are reported once it is resolved.
+    pub fn first(
+        clashes: Vec<FailedFileOperation>,
+        targets: Vec<FailedFileOperation>,
+        symlinks: Vec<FailedFileOperation>,
+    ) -> Option<Self> {
+        [
+            CheckBeforeError::Clash(clashes),
+            CheckBeforeError::Target(targets),
+            CheckBeforeError::Symlink(symlinks),
+        ]
+        .into_iter()
+        .find(|e| !e.files().is_empty())
+        .map(|mut e| {
+            e.files_mut().sort();
+            e
+        })
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CheckBeforeError::Clash(_) => "clash",
+            CheckBeforeError::Target(_) => "target",
+            CheckBeforeError::Symlink(_) => "symlink",
+        }
+    }
+
+    pub fn files(&self) -> &[FailedFileOperation] {
+        match self {
+            CheckBeforeError::Clash(files)
+            | CheckBeforeError::Target(files)
+            | CheckBeforeError::Symlink(files) => files,
+        }
+    }
+
+    fn files_mut(&mut self) -> &mut Vec<FailedFileOperation> {
+        match self {
+            CheckBeforeError::Clash(files)
+            | CheckBeforeError::Target(files)
+            | CheckBeforeError::Symlink(files) => files,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum HookError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("{0}")]
+    Failed(String),
+}
+
+#[derive(Debug, Error)]
+pub enum JournalError {
+    #[error("journal could not be accessed: {0}")]
+    Io(#[from] io::Error),
+    #[error("journal is malformed: {0}")]
+    Malformed(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum PlanEditError {
+    #[error("plan could not be edited: {0}")]
+    Io(#[from] io::Error),
+    #[error("editor '{0}' exited unsuccessfully")]
+    EditorFailed(String),
+    #[error("line {0} is malformed: '{1}'")]
+    Malformed(usize, String),
+    #[error("line {0} appears more than once")]
+    DuplicateLine(usize),
+    #[error("lines {0:?} are missing")]
+    MissingLines(Vec<usize>),
+    #[error("{} is the destination of more than one file", .0.display())]
+    DuplicateTarget(PathBuf),
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("config file {} could not be read: {1}", .0.display())]
+    Io(PathBuf, io::Error),
+    #[error("config file {} is malformed: {1}", .0.display())]
+    Malformed(PathBuf, toml::de::Error),
+    #[error("profile '{0}' is not defined in any config file")]
+    UnknownProfile(String),
+    #[error("config option '{0}' has an unsupported value, use strings, numbers, booleans or lists of them")]
+    UnsupportedValue(String),
+}
+
+#[derive(Debug, Error)]
+pub enum ExecutionError {
+    #[error(transparent)]
+    Check(#[from] CheckBeforeError),
+    #[error("before execution failed: {0}")]
+    BeforeExecute(HookError),
+    #[error("after execution failed: {0}")]
+    AfterExecute(HookError, Box<Execution>),
+}
+
+#[derive(Debug, Error)]
+pub enum PipelineError {
+    #[error("stage '{stage}' failed its checks: {error}")]
+    Check {
+        stage: String,
+        error: CheckBeforeError,
+    },
+    #[error("stage '{stage}' would fail before execution: {error}")]
+    BeforeExecute { stage: String, error: HookError },
+    #[error("stage '{stage}' would fail for {} files", .failures.len())]
+    Simulation {
+        stage: String,
+        failures: Vec<FailedFileOperation>,
+    },
+}
+
+/// Everything that can end a run of a file tool early, each kind of failure
+/// is reported with its own exit code.
+#[derive(Debug, Error)]
+pub enum FileError {
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error("failed to edit the plan: {0}")]
+    PlanEdit(#[from] PlanEditError),
+    #[error("failed to read the selection: {0}")]
+    Selection(io::Error),
+    #[error(transparent)]
+    Journal(#[from] JournalError),
+    #[error(transparent)]
+    Path(#[from] PathError),
+    #[error(transparent)]
+    Execution(#[from] ExecutionError),
+    #[error("{0} operations failed")]
+    OperationsFailed(usize),
+    #[error("{0} paths could not be scanned, refusing to run in strict mode")]
+    IncompleteScan(usize),
+    #[error("the output could not be written: {0}")]
+    Output(#[from] serde_json::Error),
+}
+
+impl FileError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            FileError::Config(_) => "config",
+            FileError::PlanEdit(_) => "plan_edit",
+            FileError::Selection(_) => "selection",
+            FileError::Journal(_) => "journal",
+            FileError::Path(_) => "path",
+            FileError::Execution(ExecutionError::Check(e)) => e.kind(),
+            FileError::Execution(ExecutionError::BeforeExecute(_)) => "before_execute",
+            FileError::Execution(ExecutionError::AfterExecute(..)) => "after_execute",
+            FileError::OperationsFailed(_) => "operations_failed",
+            FileError::IncompleteScan(_) => "incomplete_scan",
+            FileError::Output(_) => "output",
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            FileError::Config(_) => 2,
+            FileError::Journal(_) => 3,
+            FileError::Execution(ExecutionError::Check(_)) => 4,
+            FileError::Execution(ExecutionError::BeforeExecute(_)) => 5,
+            FileError::OperationsFailed(_) => 6,
+            FileError::Path(_) => 7,
+            FileError::PlanEdit(_) => 8,
+            FileError::Selection(_) => 9,
+            FileError::Execution(ExecutionError::AfterExecute(..)) => 10,
+            FileError::IncompleteScan(_) => 11,
+            FileError::Output(_) => 12,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::{
+        model::{FileOperationResult, FileOperationTask},
+        traits::Relativize,
+    };
+    use std::collections::HashSet;
+
+    #[test]
+    fn relativizing_foreign_paths_fails_with_context() {
+        let task = FileOperationTask::new(PathBuf::from("a.txt"), PathBuf::from("/work/a.txt"));
+        let e = task.relativize(Path::new("/work")).unwrap_err();
+        assert!(
+            matches!(&e, PathError::NotRelativizable { path, .. } if path == Path::new("a.txt"))
+        );
+        assert_eq!(FileError::from(e).exit_code(), 7);
+    }
+
+    #[test]
+    fn failures_have_distinct_exit_codes() {
+        let execution = Execution {
+            result: FileOperationResult::new(0, 0, vec![]),
+            failures: vec![],
+            journal: Ok(None),
+        };
+        // one error of every variant
+        let errors = [
+            FileError::Config(ConfigError::UnknownProfile("photos".to_string())),
+            FileError::PlanEdit(PlanEditError::DuplicateLine(1)),
+            FileError::Selection(io::ErrorKind::UnexpectedEof.into()),
+            FileError::Journal(JournalError::Io(io::ErrorKind::NotFound.into())),
+            FileError::Path(PathError::InvalidUtf8(PathBuf::from("x"))),
+            ExecutionError::Check(CheckBeforeError::Clash(vec![])).into(),
+            ExecutionError::BeforeExecute(HookError::Failed("no".to_string())).into(),
+            ExecutionError::AfterExecute(HookError::Failed("no".to_string()), Box::new(execution))
+                .into(),
+            FileError::OperationsFailed(1),
+            FileError::IncompleteScan(1),
+            FileError::Output(serde_json::from_str::<u32>("").unwrap_err()),
+        ];
+        let codes = errors
+            .iter()
+            .map(FileError::exit_code)
+            .collect::<HashSet<_>>();
+        assert_eq!(codes.len(), errors.len());
+        // 0 is success and 1 is used for invalid command lines
+        assert!(codes.iter().all(|code| *code > 1));
+    }
 }
@@ -0,0 +1,147 @@
+use super::{
+    errors::{ExecutionError, JournalError},
+    filesystem::FileSystem,
+    journal::{state_dir, Journal, JournalEntry},
+    model::{FailedFileOperation, FileOperationResult},
+    progress::{NoProgress, Progress},
+    traits::FileOperation,
+};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub struct Execution {
+    pub result: FileOperationResult,
+    pub failures: Vec<FailedFileOperation>,
+    pub journal: Result<Option<PathBuf>, JournalError>,
+}
+
+/// Executes a planned file operation without printing anything, the library
+/// counterpart of `Runnable`.
+pub struct Executor<'a> {
+    atomic: bool,
+    jobs: usize,
+    progress: &'a dyn Progress,
+    journal: Option<(String, PathBuf)>,
+    journal_dir: Option<PathBuf>,
+}
+
+impl Default for Executor<'_> {
+    fn default() -> Self {
+        Self {
+            atomic: false,
+            jobs: 1,
+            progress: &NoProgress,
+            journal: None,
+            journal_dir: None,
+        }
+    }
+}
+
+impl<'a> Executor<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn atomic(mut self, atomic: bool) -> Self {
+        self.atomic = atomic;
+        self
+    }
+
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    pub fn progress<'b>(self, progress: &'b dyn Progress) -> Executor<'b> {
+        Executor {
+            atomic: self.atomic,
+            jobs: self.jobs,
+            progress,
+            journal: self.journal,
+            journal_dir: self.journal_dir,
+        }
+    }
+
+    pub fn journal(mut self, tool: &str, working_dir: &Path) -> Self {
+        self.journal = Some((tool.to_string(), working_dir.to_path_buf()));
+        self
+    }
+
+    /// Saves journals to `dir` instead of the state folder of `state_dir`.
+    pub fn journal_dir(mut self, dir: &Path) -> Self {
+        self.journal_dir = Some(dir.to_path_buf());
+        self
+    }
+
+    pub(crate) fn without_journal(&self) -> Executor<'a> {
+        Executor {
+            atomic: self.atomic,
+            jobs: self.jobs,
+            progress: self.progress,
+            journal: None,
+            journal_dir: None,
+        }
+    }
+
+    pub(crate) fn journal_tool(&self) -> Option<&str> {
+        self.journal.as_ref().map(|(tool, _)| tool.as_str())
+    }
+
+    // falls back to the working dir when there is no state folder
+    pub(crate) fn save_journal(
+        &self,
+        fs: &dyn FileSystem,
+        tool: &str,
+        working_dir: &Path,
+        entries: Vec<JournalEntry>,
+    ) -> Result<PathBuf, JournalError> {
+        let dir = self
+            .journal_dir
+            .clone()
+            .or_else(state_dir)
+            .unwrap_or_else(|| working_dir.to_path_buf());
+        Journal::new(tool, working_dir, entries).save(fs, &dir)
+    }
+
+    pub fn run<C, O: FileOperation<C>>(
+        &self,
+        operation: &mut O,
+    ) -> Result<Execution, ExecutionError> {
+        if let Some(e) = operation.check_before_execution() {
+            return Err(e.into());
+        }
+        operation
+            .before_execute()
+            .map_err(ExecutionError::BeforeExecute)?;
+        let execution = self.apply(operation);
+        match operation.after_execute() {
+            Ok(_) => Ok(execution),
+            Err(e) => Err(ExecutionError::AfterExecute(e, Box::new(execution))),
+        }
+    }
+
+    pub fn apply<C, O: FileOperation<C>>(&self, operation: &mut O) -> Execution {
+        let result = if self.atomic {
+            operation.execute_transactional_with(self.jobs, self.progress)
+        } else {
+            operation.execute_with(self.jobs, self.progress)
+        };
+        let failures = operation.get_failed_operations();
+        let journal = match &self.journal {
+            Some((tool, working_dir)) if !result.journal.is_empty() => self
+                .save_journal(
+                    operation.get_fs(),
+                    tool,
+                    working_dir,
+                    result.journal.clone(),
+                )
+                .map(Some),
+            _ => Ok(None),
+        };
+        Execution {
+            result,
+            failures,
+            journal,
+        }
+    }
+}
@@ -0,0 +1,931 @@
+use super::{errors::PathError, normalize};
+use crc32fast::Hasher;
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{self, File, FileTimes},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+use walkdir::WalkDir;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileMetadata {
+    pub is_dir: bool,
+    pub len: u64,
+    pub modified: SystemTime,
+}
+
+pub trait FileSystem: Send + Sync {
+    /// Walks `root` down to `depth`, listing the paths that could be read and
+    /// the errors for the ones that could not. Linked directories are only
+    /// descended into with `follow_links`, links back to an ancestor are
+    /// reported as errors.
+    fn scan(
+        &self,
+        root: &Path,
+        depth: Option<usize>,
+        follow_links: bool,
+    ) -> (Vec<PathBuf>, Vec<PathError>) {
+        self.scan_filtered(root, depth, follow_links, &mut |_, _| true)
+    }
+
+    /// Like `scan`, but leaves out the paths `keep` rejects and does not read
+    /// the rejected directories. `keep` gets every path with whether it is a
+    /// directory, a directory always before its contents.
+    fn scan_filtered(
+        &self,
+        root: &Path,
+        depth: Option<usize>,
+        follow_links: bool,
+        keep: &mut dyn FnMut(&Path, bool) -> bool,
+    ) -> (Vec<PathBuf>, Vec<PathError>);
+
+    fn walk(&self, root: &Path, depth: Option<usize>) -> Vec<PathBuf> {
+        self.scan(root, depth, false).0
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf>;
+
+    fn is_symlink(&self, path: &Path) -> bool {
+        self.read_link(path).is_ok()
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FileMetadata>;
+
+    fn exists(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok()
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok_and(|metadata| !metadata.is_dir)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok_and(|metadata| metadata.is_dir)
+    }
+
+    fn is_hidden(&self, path: &Path) -> io::Result<bool> {
+        Ok(path
+            .file_name()
+            .is_some_and(|name| name.as_encoded_bytes().starts_with(b".")))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()>;
+
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    /// Creates a link at `link` pointing to `target`, which does not have to exist.
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()>;
+
+    /// Copies the file at `from` to `to`, fails if `to` exists already.
+    fn copy_file(&self, from: &Path, to: &Path) -> io::Result<u64>;
+
+    fn checksum(&self, path: &Path) -> io::Result<u32>;
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String>;
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+}
+
+pub struct RealFs;
+
+impl FileSystem for RealFs {
+    fn scan_filtered(
+        &self,
+        root: &Path,
+        depth: Option<usize>,
+        follow_links: bool,
+        keep: &mut dyn FnMut(&Path, bool) -> bool,
+    ) -> (Vec<PathBuf>, Vec<PathError>) {
+        let dir_iter = if let Some(depth) = depth {
+            WalkDir::new(root).max_depth(depth)
+        } else {
+            WalkDir::new(root)
+        }
+        .follow_links(follow_links)
+        .into_iter()
+        .filter_entry(|entry| keep(entry.path(), entry.file_type().is_dir()));
+        let mut paths = vec![];
+        let mut errors = vec![];
+        for entry in dir_iter {
+            match entry {
+                Ok(entry) => paths.push(entry.into_path()),
+                Err(e) => errors.push(PathError::from_walk(e, root)),
+            }
+        }
+        (paths, errors)
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::read_link(path)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FileMetadata> {
+        let metadata = fs::metadata(path)?;
+        Ok(FileMetadata {
+            is_dir: metadata.is_dir(),
+            len: metadata.len(),
+            modified: metadata.modified()?,
+        })
+    }
+
+    #[cfg(windows)]
+    fn is_hidden(&self, path: &Path) -> io::Result<bool> {
+        use std::os::windows::prelude::*;
+        let attributes = fs::metadata(path)?.file_attributes();
+        Ok((attributes & 0x2) > 0)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    #[cfg(unix)]
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+        std::os::unix::fs::symlink(target, link)
+    }
+
+    #[cfg(windows)]
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+        // relative targets are resolved from the folder holding the link
+        let resolved = link.parent().unwrap_or(Path::new("")).join(target);
+        if resolved.is_dir() {
+            std::os::windows::fs::symlink_dir(target, link)
+        } else {
+            std::os::windows::fs::symlink_file(target, link)
+        }
+    }
+
+    fn copy_file(&self, from: &Path, to: &Path) -> io::Result<u64> {
+        let mut source = File::open(from)?;
+        let metadata = source.metadata()?;
+        // created exclusively, so a file that appeared at `to` is never replaced
+        let mut copy = File::options().write(true).create_new(true).open(to)?;
+        let bytes = io::copy(&mut source, &mut copy)?;
+        copy.set_permissions(metadata.permissions())?;
+        copy.set_times(
+            FileTimes::new()
+                .set_accessed(metadata.accessed()?)
+                .set_modified(metadata.modified()?),
+        )?;
+        Ok(bytes)
+    }
+
+    fn checksum(&self, path: &Path) -> io::Result<u32> {
+        let mut file = File::open(path)?;
+        let mut hasher = Hasher::new();
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+        Ok(hasher.finalize())
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        fs::write(path, contents)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Contents {
+    Bytes(Vec<u8>),
+    // contents of a snapshotted file, read from the backing file system on demand
+    Backed { path: PathBuf, len: u64 },
+}
+
+impl Contents {
+    fn len(&self) -> u64 {
+        match self {
+            Contents::Bytes(bytes) => bytes.len() as u64,
+            Contents::Backed { len, .. } => *len,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum MemoryEntry {
+    Dir,
+    File {
+        contents: Contents,
+        modified: SystemTime,
+    },
+    Link {
+        target: PathBuf,
+    },
+}
+
+const MAX_LINK_HOPS: usize = 40;
+
+// follows the links in every component of `path`, like a lookup by the OS
+fn resolve(entries: &BTreeMap<PathBuf, MemoryEntry>, path: &Path) -> io::Result<PathBuf> {
+    let mut resolved = PathBuf::new();
+    let mut hops = 0;
+    for component in path.components() {
+        resolved.push(component);
+        while let Some(MemoryEntry::Link { target }) = entries.get(&resolved) {
+            hops += 1;
+            if hops > MAX_LINK_HOPS {
+                return Err(io::Error::other(format!(
+                    "too many levels of symbolic links: {}",
+                    path.display()
+                )));
+            }
+            resolved = normalize(&match resolved.parent() {
+                Some(parent) => parent.join(target),
+                None => target.clone(),
+            });
+        }
+    }
+    Ok(resolved)
+}
+
+#[derive(Default)]
+pub struct MemoryFs {
+    entries: Mutex<BTreeMap<PathBuf, MemoryEntry>>,
+    failing: Mutex<HashSet<PathBuf>>,
+    unreadable: Mutex<HashSet<PathBuf>>,
+    mounts: Mutex<Vec<PathBuf>>,
+    backing: Option<Arc<dyn FileSystem>>,
+}
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn snapshot(base: Arc<dyn FileSystem>, root: &Path) -> Self {
+        let paths = base.walk(root, None);
+        Self::snapshot_paths(base, root, paths)
+    }
+
+    /// Like `snapshot`, but only copies `paths`, e.g. the result of a
+    /// filtered scan. Directories are copied without their contents.
+    pub fn snapshot_paths(
+        base: Arc<dyn FileSystem>,
+        root: &Path,
+        paths: impl IntoIterator<Item = PathBuf>,
+    ) -> Self {
+        let snapshot = Self {
+            backing: Some(base.clone()),
+            ..Self::default()
+        };
+        snapshot.add_dir(root);
+        for path in paths {
+            if let Ok(target) = base.read_link(&path) {
+                snapshot.add_link(&path, target);
+                continue;
+            }
+            match base.metadata(&path) {
+                Ok(metadata) if metadata.is_dir => snapshot.add_dir(&path),
+                Ok(metadata) => {
+                    if let Some(parent) = path.parent() {
+                        snapshot.add_dir(parent);
+                    }
+                    let contents = Contents::Backed {
+                        path: path.clone(),
+                        len: metadata.len,
+                    };
+                    snapshot
+                        .entries
+                        .lock()
+                        .expect("memory fs lock poisoned")
+                        .insert(
+                            path,
+                            MemoryEntry::File {
+                                contents,
+                                modified: metadata.modified,
+                            },
+                        );
+                }
+                Err(_) => {}
+            }
+        }
+        snapshot
+    }
+
+    fn backing(&self, path: &Path) -> io::Result<&dyn FileSystem> {
+        self.backing.as_deref().ok_or_else(|| not_found(path))
+    }
+
+    pub fn add_dir(&self, path: impl AsRef<Path>) {
+        let mut entries = self.entries.lock().expect("memory fs lock poisoned");
+        for ancestor in path.as_ref().ancestors() {
+            if ancestor.as_os_str().is_empty() {
+                continue;
+            }
+            entries
+                .entry(ancestor.to_path_buf())
+                .or_insert(MemoryEntry::Dir);
+        }
+    }
+
+    pub fn add_file(&self, path: impl AsRef<Path>, contents: &str) {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            self.add_dir(parent);
+        }
+        self.entries
+            .lock()
+            .expect("memory fs lock poisoned")
+            .insert(
+                path.to_path_buf(),
+                MemoryEntry::File {
+                    contents: Contents::Bytes(contents.as_bytes().to_vec()),
+                    modified: SystemTime::now(),
+                },
+            );
+    }
+
+    pub fn add_link(&self, path: impl AsRef<Path>, target: impl AsRef<Path>) {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            self.add_dir(parent);
+        }
+        self.entries
+            .lock()
+            .expect("memory fs lock poisoned")
+            .insert(
+                path.to_path_buf(),
+                MemoryEntry::Link {
+                    target: target.as_ref().to_path_buf(),
+                },
+            );
+    }
+
+    pub fn set_modified(&self, path: impl AsRef<Path>, time: SystemTime) {
+        let mut entries = self.entries.lock().expect("memory fs lock poisoned");
+        if let Some(MemoryEntry::File { modified, .. }) = entries.get_mut(path.as_ref()) {
+            *modified = time;
+        }
+    }
+
+    pub fn fail_on(&self, path: impl AsRef<Path>) {
+        self.failing
+            .lock()
+            .expect("memory fs lock poisoned")
+            .insert(path.as_ref().to_path_buf());
+    }
+
+    /// Makes the contents of the directory at `path` unlistable for walks.
+    pub fn deny_read(&self, path: impl AsRef<Path>) {
+        self.unreadable
+            .lock()
+            .expect("memory fs lock poisoned")
+            .insert(path.as_ref().to_path_buf());
+    }
+
+    pub fn add_mount(&self, path: impl AsRef<Path>) {
+        self.add_dir(path.as_ref());
+        self.mounts
+            .lock()
+            .expect("memory fs lock poisoned")
+            .push(path.as_ref().to_path_buf());
+    }
+
+    fn device_of(&self, path: &Path) -> Option<PathBuf> {
+        self.mounts
+            .lock()
+            .expect("memory fs lock poisoned")
+            .iter()
+            .filter(|mount| path.starts_with(mount))
+            .max_by_key(|mount| mount.components().count())
+            .cloned()
+    }
+
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.entries
+            .lock()
+            .expect("memory fs lock poisoned")
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.entries
+            .lock()
+            .expect("memory fs lock poisoned")
+            .iter()
+            .filter(|(_, entry)| matches!(entry, MemoryEntry::File { .. }))
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
+    fn check_failing(&self, paths: &[&Path]) -> io::Result<()> {
+        let failing = self.failing.lock().expect("memory fs lock poisoned");
+        match paths.iter().find(|path| failing.contains(**path)) {
+            Some(path) => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("permission denied: {}", path.display()),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn insert_file(&self, path: &Path, contents: Contents, modified: SystemTime) -> io::Result<()> {
+        self.check_failing(&[path])?;
+        let mut entries = self.entries.lock().expect("memory fs lock poisoned");
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() && entries.get(parent) != Some(&MemoryEntry::Dir) {
+                return Err(not_found(parent));
+            }
+        }
+        if entries.get(path) == Some(&MemoryEntry::Dir) {
+            return Err(io::Error::new(
+                io::ErrorKind::IsADirectory,
+                format!("is a directory: {}", path.display()),
+            ));
+        }
+        entries.insert(path.to_path_buf(), MemoryEntry::File { contents, modified });
+        Ok(())
+    }
+}
+
+struct MemoryScan<'a> {
+    entries: &'a BTreeMap<PathBuf, MemoryEntry>,
+    unreadable: &'a HashSet<PathBuf>,
+    depth: Option<usize>,
+    follow_links: bool,
+    keep: &'a mut dyn FnMut(&Path, bool) -> bool,
+    paths: Vec<PathBuf>,
+    errors: Vec<PathError>,
+}
+
+impl MemoryScan<'_> {
+    // lists the children of `real` as if they were in `shown`, which differs
+    // from `real` below a followed link
+    fn visit(&mut self, real: &Path, shown: &Path, level: usize, ancestors: &mut Vec<PathBuf>) {
+        if self.depth.is_some_and(|depth| level >= depth) {
+            return;
+        }
+        if self.unreadable.contains(real) {
+            self.errors
+                .push(PathError::PermissionDenied(shown.to_path_buf()));
+            return;
+        }
+        let children = self
+            .entries
+            .iter()
+            .filter(|(path, _)| path.parent() == Some(real))
+            .map(|(path, entry)| (path.clone(), entry.clone()))
+            .collect::<Vec<_>>();
+        for (child, entry) in children {
+            let shown_child = shown.join(child.file_name().unwrap_or_default());
+            let target = match entry {
+                MemoryEntry::Dir => Some(child),
+                MemoryEntry::Link { .. } if self.follow_links => resolve(self.entries, &child)
+                    .ok()
+                    .filter(|target| self.entries.get(target) == Some(&MemoryEntry::Dir)),
+                _ => None,
+            };
+            if !(self.keep)(&shown_child, target.is_some()) {
+                continue;
+            }
+            self.paths.push(shown_child.clone());
+            let Some(target) = target else {
+                continue;
+            };
+            if ancestors.contains(&target) {
+                self.errors.push(PathError::Walk {
+                    path: shown_child,
+                    message: format!("symlink loop back to {}", target.display()),
+                });
+                continue;
+            }
+            ancestors.push(target.clone());
+            self.visit(&target, &shown_child, level + 1, ancestors);
+            ancestors.pop();
+        }
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no such file or directory: {}", path.display()),
+    )
+}
+
+impl FileSystem for MemoryFs {
+    fn scan_filtered(
+        &self,
+        root: &Path,
+        depth: Option<usize>,
+        follow_links: bool,
+        keep: &mut dyn FnMut(&Path, bool) -> bool,
+    ) -> (Vec<PathBuf>, Vec<PathError>) {
+        // `keep` may read from this file system, so the locks are not held during the walk
+        let entries = self
+            .entries
+            .lock()
+            .expect("memory fs lock poisoned")
+            .clone();
+        let unreadable = self
+            .unreadable
+            .lock()
+            .expect("memory fs lock poisoned")
+            .clone();
+        let root_is_dir = self.is_dir(root);
+        let mut scan = MemoryScan {
+            entries: &entries,
+            unreadable: &unreadable,
+            depth,
+            follow_links,
+            keep,
+            paths: vec![],
+            errors: vec![],
+        };
+        if entries.contains_key(root) && (scan.keep)(root, root_is_dir) {
+            scan.paths.push(root.to_path_buf());
+            let real = if follow_links {
+                resolve(&entries, root).unwrap_or_else(|_| root.to_path_buf())
+            } else {
+                root.to_path_buf()
+            };
+            scan.visit(&real, root, 0, &mut vec![real.clone()]);
+        }
+        (scan.paths, scan.errors)
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        match self
+            .entries
+            .lock()
+            .expect("memory fs lock poisoned")
+            .get(path)
+        {
+            Some(MemoryEntry::Link { target }) => Ok(target.clone()),
+            Some(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("not a symbolic link: {}", path.display()),
+            )),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FileMetadata> {
+        let entries = self.entries.lock().expect("memory fs lock poisoned");
+        match entries.get(&resolve(&entries, path)?) {
+            Some(MemoryEntry::Dir) => Ok(FileMetadata {
+                is_dir: true,
+                len: 0,
+                modified: SystemTime::UNIX_EPOCH,
+            }),
+            Some(MemoryEntry::File { contents, modified }) => Ok(FileMetadata {
+                is_dir: false,
+                len: contents.len(),
+                modified: *modified,
+            }),
+            Some(MemoryEntry::Link { .. }) | None => Err(not_found(path)),
+        }
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.check_failing(&[from, to])?;
+        if self.device_of(from) != self.device_of(to) {
+            return Err(io::Error::new(
+                io::ErrorKind::CrossesDevices,
+                "invalid cross-device link",
+            ));
+        }
+        let mut entries = self.entries.lock().expect("memory fs lock poisoned");
+        let entry = entries.get(from).cloned().ok_or_else(|| not_found(from))?;
+        if let Some(parent) = to.parent() {
+            if !parent.as_os_str().is_empty() && entries.get(parent) != Some(&MemoryEntry::Dir) {
+                return Err(not_found(parent));
+            }
+        }
+        if from == to {
+            return Ok(());
+        }
+        if to.starts_with(from) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot move a directory into itself",
+            ));
+        }
+        match (&entry, entries.get(to)) {
+            (MemoryEntry::File { .. }, Some(MemoryEntry::Dir)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::IsADirectory,
+                    format!("is a directory: {}", to.display()),
+                ))
+            }
+            (MemoryEntry::Dir, Some(MemoryEntry::File { .. })) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotADirectory,
+                    format!("not a directory: {}", to.display()),
+                ))
+            }
+            (MemoryEntry::Dir, Some(MemoryEntry::Dir))
+                if entries.keys().any(|path| path.parent() == Some(to)) =>
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::DirectoryNotEmpty,
+                    format!("directory not empty: {}", to.display()),
+                ))
+            }
+            _ => {}
+        }
+        let moved = entries
+            .keys()
+            .filter(|path| path.starts_with(from))
+            .cloned()
+            .collect::<Vec<_>>();
+        for path in moved {
+            if let Some(entry) = entries.remove(&path) {
+                let relative = path.strip_prefix(from).expect("path is under the source");
+                // joining an empty path would add a trailing separator
+                let moved_to = if relative.as_os_str().is_empty() {
+                    to.to_path_buf()
+                } else {
+                    to.join(relative)
+                };
+                entries.insert(moved_to, entry);
+            }
+        }
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.check_failing(&[path])?;
+        if self.is_file(path) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("file exists: {}", path.display()),
+            ));
+        }
+        self.add_dir(path);
+        Ok(())
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        self.check_failing(&[path])?;
+        let mut entries = self.entries.lock().expect("memory fs lock poisoned");
+        match entries.get(path) {
+            Some(MemoryEntry::Dir) => {
+                if entries.keys().any(|other| other.parent() == Some(path)) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::DirectoryNotEmpty,
+                        format!("directory not empty: {}", path.display()),
+                    ));
+                }
+                entries.remove(path);
+                Ok(())
+            }
+            Some(_) => Err(io::Error::new(
+                io::ErrorKind::NotADirectory,
+                format!("not a directory: {}", path.display()),
+            )),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.check_failing(&[path])?;
+        let mut entries = self.entries.lock().expect("memory fs lock poisoned");
+        match entries.get(path) {
+            Some(MemoryEntry::File { .. } | MemoryEntry::Link { .. }) => {
+                entries.remove(path);
+                Ok(())
+            }
+            Some(MemoryEntry::Dir) => Err(io::Error::new(
+                io::ErrorKind::IsADirectory,
+                format!("is a directory: {}", path.display()),
+            )),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+        self.check_failing(&[link])?;
+        let mut entries = self.entries.lock().expect("memory fs lock poisoned");
+        if let Some(parent) = link.parent() {
+            if !parent.as_os_str().is_empty() && entries.get(parent) != Some(&MemoryEntry::Dir) {
+                return Err(not_found(parent));
+            }
+        }
+        if entries.contains_key(link) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("file exists: {}", link.display()),
+            ));
+        }
+        entries.insert(
+            link.to_path_buf(),
+            MemoryEntry::Link {
+                target: target.to_path_buf(),
+            },
+        );
+        Ok(())
+    }
+
+    fn copy_file(&self, from: &Path, to: &Path) -> io::Result<u64> {
+        self.check_failing(&[to])?;
+        let entry = {
+            let entries = self.entries.lock().expect("memory fs lock poisoned");
+            entries.get(&resolve(&entries, from)?).cloned()
+        };
+        let Some(MemoryEntry::File { contents, modified }) = entry else {
+            return Err(not_found(from));
+        };
+        if self.exists(to) || self.is_symlink(to) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("file exists: {}", to.display()),
+            ));
+        }
+        let len = contents.len();
+        self.insert_file(to, contents, modified)?;
+        Ok(len)
+    }
+
+    fn checksum(&self, path: &Path) -> io::Result<u32> {
+        let entries = self.entries.lock().expect("memory fs lock poisoned");
+        match entries.get(&resolve(&entries, path)?) {
+            Some(MemoryEntry::File {
+                contents: Contents::Bytes(bytes),
+                ..
+            }) => Ok(crc32fast::hash(bytes)),
+            Some(MemoryEntry::File {
+                contents: Contents::Backed { path, .. },
+                ..
+            }) => self.backing(path)?.checksum(path),
+            Some(MemoryEntry::Dir) => Err(io::Error::new(
+                io::ErrorKind::IsADirectory,
+                format!("is a directory: {}", path.display()),
+            )),
+            Some(MemoryEntry::Link { .. }) | None => Err(not_found(path)),
+        }
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        let entries = self.entries.lock().expect("memory fs lock poisoned");
+        match entries.get(&resolve(&entries, path)?) {
+            Some(MemoryEntry::File {
+                contents: Contents::Bytes(bytes),
+                ..
+            }) => String::from_utf8(bytes.clone())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Some(MemoryEntry::File {
+                contents: Contents::Backed { path, .. },
+                ..
+            }) => self.backing(path)?.read_to_string(path),
+            Some(MemoryEntry::Dir) => Err(io::Error::new(
+                io::ErrorKind::IsADirectory,
+                format!("is a directory: {}", path.display()),
+            )),
+            Some(MemoryEntry::Link { .. }) | None => Err(not_found(path)),
+        }
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.insert_file(path, Contents::Bytes(contents.to_vec()), SystemTime::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fs() -> MemoryFs {
+        let fs = MemoryFs::new();
+        fs.add_file("/root/a.txt", "a");
+        fs.add_file("/root/dir/b.txt", "b");
+        fs.add_file("/root/dir/nested/c.txt", "c");
+        fs
+    }
+
+    #[test]
+    fn walk_respects_depth() {
+        let fs = fs();
+        assert_eq!(
+            fs.walk(Path::new("/root"), Some(1)),
+            vec![
+                PathBuf::from("/root"),
+                PathBuf::from("/root/a.txt"),
+                PathBuf::from("/root/dir"),
+            ]
+        );
+        assert_eq!(fs.walk(Path::new("/root"), None).len(), 6);
+        assert!(fs.walk(Path::new("/missing"), None).is_empty());
+    }
+
+    #[test]
+    fn scan_reports_unreadable_directories() {
+        let fs = fs();
+        fs.deny_read("/root/dir");
+        let (paths, errors) = fs.scan(Path::new("/root"), None, false);
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("/root"),
+                PathBuf::from("/root/a.txt"),
+                PathBuf::from("/root/dir"),
+            ]
+        );
+        assert!(matches!(
+            errors.as_slice(),
+            [PathError::PermissionDenied(dir)] if dir == Path::new("/root/dir")
+        ));
+        assert!(fs.scan(Path::new("/root"), Some(1), false).1.is_empty());
+    }
+
+    #[test]
+    fn scan_filtered_does_not_read_rejected_directories() {
+        let fs = fs();
+        fs.deny_read("/root/dir");
+        let mut seen = vec![];
+        let (paths, errors) =
+            fs.scan_filtered(Path::new("/root"), None, false, &mut |path, is_dir| {
+                seen.push((path.to_path_buf(), is_dir));
+                path != Path::new("/root/dir")
+            });
+        assert_eq!(
+            paths,
+            vec![PathBuf::from("/root"), PathBuf::from("/root/a.txt")]
+        );
+        assert!(errors.is_empty());
+        assert_eq!(
+            seen,
+            vec![
+                (PathBuf::from("/root"), true),
+                (PathBuf::from("/root/a.txt"), false),
+                (PathBuf::from("/root/dir"), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn rename_moves_files_and_directories() {
+        let fs = fs();
+        fs.rename(Path::new("/root/dir/b.txt"), Path::new("/root/b.txt"))
+            .unwrap();
+        assert!(fs.is_file(Path::new("/root/b.txt")));
+        assert!(!fs.exists(Path::new("/root/dir/b.txt")));
+
+        fs.rename(Path::new("/root/dir"), Path::new("/root/moved"))
+            .unwrap();
+        assert!(fs.is_file(Path::new("/root/moved/nested/c.txt")));
+        assert!(!fs.exists(Path::new("/root/dir")));
+    }
+
+    #[test]
+    fn rename_fails_like_a_real_filesystem() {
+        let fs = fs();
+        let missing = fs.rename(Path::new("/root/missing"), Path::new("/root/x"));
+        assert_eq!(missing.unwrap_err().kind(), io::ErrorKind::NotFound);
+        let no_parent = fs.rename(Path::new("/root/a.txt"), Path::new("/root/none/a.txt"));
+        assert_eq!(no_parent.unwrap_err().kind(), io::ErrorKind::NotFound);
+        let onto_dir = fs.rename(Path::new("/root/a.txt"), Path::new("/root/dir"));
+        assert_eq!(onto_dir.unwrap_err().kind(), io::ErrorKind::IsADirectory);
+    }
+
+    #[test]
+    fn injected_failures_are_reported() {
+        let fs = fs();
+        fs.fail_on("/root/a.txt");
+        let result = fs.rename(Path::new("/root/a.txt"), Path::new("/root/b.txt"));
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert!(fs.exists(Path::new("/root/a.txt")));
+    }
+
+    #[test]
+    fn remove_dir_only_removes_empty_directories() {
+        let fs = fs();
+        let not_empty = fs.remove_dir(Path::new("/root/dir"));
+        assert_eq!(
+            not_empty.unwrap_err().kind(),
+            io::ErrorKind::DirectoryNotEmpty
+        );
+        fs.add_dir("/root/empty");
+        fs.remove_dir(Path::new("/root/empty")).unwrap();
+        assert!(!fs.exists(Path::new("/root/empty")));
+    }
+}
@@ -0,0 +1,121 @@
+use globset::{Glob, GlobMatcher};
+use regex::Regex;
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+#[derive(Debug, Clone)]
+pub struct PathPattern {
+    matcher: GlobMatcher,
+    file_name_only: bool,
+}
+
+impl PathPattern {
+    pub fn is_match(&self, relative_path: &Path) -> bool {
+        if self.file_name_only {
+            relative_path
+                .file_name()
+                .is_some_and(|name| self.matcher.is_match(name))
+        } else {
+            self.matcher.is_match(relative_path)
+        }
+    }
+}
+
+impl FromStr for PathPattern {
+    type Err = globset::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self {
+            matcher: Glob::new(s)?.compile_matcher(),
+            file_name_only: !s.contains('/'),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PathFilter {
+    include: Vec<PathPattern>,
+    exclude: Vec<PathPattern>,
+    matches: Vec<Regex>,
+}
+
+impl PathFilter {
+    pub fn new(include: Vec<PathPattern>, exclude: Vec<PathPattern>, matches: Vec<Regex>) -> Self {
+        Self {
+            include,
+            exclude,
+            matches,
+        }
+    }
+
+    pub fn is_match(&self, relative_path: &Path) -> bool {
+        let included = self.include.is_empty()
+            || self
+                .include
+                .iter()
+                .any(|pattern| pattern.is_match(relative_path));
+        let excluded = self
+            .exclude
+            .iter()
+            .any(|pattern| pattern.is_match(relative_path));
+        let matched = self
+            .matches
+            .iter()
+            .all(|regex| regex.is_match(&relative_path.to_string_lossy()));
+        included && !excluded && matched
+    }
+
+    pub fn apply(&self, working_dir: &Path, files: Vec<PathBuf>) -> Vec<PathBuf> {
+        files
+            .into_iter()
+            .filter(|file| {
+                let relative_path = file.strip_prefix(working_dir).unwrap_or(file);
+                self.is_match(relative_path)
+            })
+            .collect::<Vec<_>>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(include: &[&str], exclude: &[&str], matches: &[&str]) -> PathFilter {
+        PathFilter::new(
+            include.iter().map(|glob| glob.parse().unwrap()).collect(),
+            exclude.iter().map(|glob| glob.parse().unwrap()).collect(),
+            matches.iter().map(|regex| regex.parse().unwrap()).collect(),
+        )
+    }
+
+    #[test]
+    fn patterns_without_separator_match_the_file_name() {
+        let filter = filter(&["IMG_*.jpg"], &[], &[]);
+        assert!(filter.is_match(Path::new("IMG_01.jpg")));
+        assert!(filter.is_match(Path::new("trip/IMG_02.jpg")));
+        assert!(!filter.is_match(Path::new("trip/DSC_02.jpg")));
+    }
+
+    #[test]
+    fn excludes_win_over_includes() {
+        let filter = filter(&["*.js"], &["node_modules/**"], &[]);
+        assert!(filter.is_match(Path::new("src/index.js")));
+        assert!(!filter.is_match(Path::new("node_modules/pkg/index.js")));
+    }
+
+    #[test]
+    fn regexes_are_applied_to_the_relative_path() {
+        let filter = filter(&[], &[], &[r"^season\d+/"]);
+        let working_dir = Path::new("/shows");
+        let files = vec![
+            PathBuf::from("/shows/season1/ep01.mkv"),
+            PathBuf::from("/shows/extras/ep01.mkv"),
+        ];
+        assert_eq!(
+            filter.apply(working_dir, files),
+            vec![PathBuf::from("/shows/season1/ep01.mkv")]
+        );
+    }
+}
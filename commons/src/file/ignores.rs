@@ -0,0 +1,106 @@
+use super::filesystem::FileSystem;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+pub const IGNORE_FILES: &[&str] = &[".gitignore", ".ignore"];
+
+pub struct IgnoreRules {
+    root: PathBuf,
+    matchers: HashMap<PathBuf, Gitignore>,
+}
+
+impl IgnoreRules {
+    /// The rules for a scan of `root`, starting with the ignore files of the
+    /// folders above it up to the root of its repository, like git and
+    /// ripgrep read them.
+    pub fn new(fs: &dyn FileSystem, root: &Path, tool_ignore_file: &str) -> Self {
+        let mut rules = Self {
+            root: root.to_path_buf(),
+            matchers: HashMap::new(),
+        };
+        if let Some(repo) = root.ancestors().find(|dir| fs.exists(&dir.join(".git"))) {
+            for dir in root.ancestors().skip(1).filter(|dir| dir.starts_with(repo)) {
+                rules.add_dir(fs, dir, tool_ignore_file);
+            }
+        }
+        rules
+    }
+
+    /// Reads the ignore files of `dir`, the rules apply to the paths below it.
+    pub fn add_dir(&mut self, fs: &dyn FileSystem, dir: &Path, tool_ignore_file: &str) {
+        let mut builder = GitignoreBuilder::new(dir);
+        let mut has_rules = false;
+        // later files take precedence, so the tool specific one can override the generic ones
+        for file_name in IGNORE_FILES.iter().chain([&tool_ignore_file]) {
+            let Ok(content) = fs.read_to_string(&dir.join(file_name)) else {
+                continue;
+            };
+            for line in content.lines() {
+                has_rules |= builder.add_line(None, line).is_ok();
+            }
+        }
+        if has_rules {
+            if let Ok(matcher) = builder.build() {
+                self.matchers.insert(dir.to_path_buf(), matcher);
+            }
+        }
+    }
+
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        // the root itself may be inside a `.git` folder
+        if path
+            .strip_prefix(&self.root)
+            .unwrap_or(path)
+            .components()
+            .any(|component| component.as_os_str() == ".git")
+        {
+            return true;
+        }
+        for dir in path.ancestors().skip(1) {
+            if let Some(matcher) = self.matchers.get(dir) {
+                let matched = matcher.matched_path_or_any_parents(path, is_dir);
+                if matched.is_ignore() {
+                    return true;
+                }
+                if matched.is_whitelist() {
+                    return false;
+                }
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::filesystem::MemoryFs;
+
+    #[test]
+    fn reads_the_ignore_files_above_the_root_up_to_the_repository() {
+        let fs = MemoryFs::new();
+        fs.add_file("/.gitignore", "*.txt\n");
+        fs.add_dir("/repo/.git");
+        fs.add_file("/repo/.gitignore", "*.log\n");
+        fs.add_file("/repo/src/.ignore", "*.tmp\n!keep.log\n");
+        let rules = IgnoreRules::new(&fs, Path::new("/repo/src/work"), ".toolignore");
+        assert!(rules.is_ignored(Path::new("/repo/src/work/run.log"), false));
+        assert!(rules.is_ignored(Path::new("/repo/src/work/a/b.tmp"), false));
+        assert!(!rules.is_ignored(Path::new("/repo/src/work/keep.log"), false));
+        assert!(!rules.is_ignored(Path::new("/repo/src/work/notes.txt"), false));
+
+        let outside = IgnoreRules::new(&fs, Path::new("/other"), ".toolignore");
+        assert!(!outside.is_ignored(Path::new("/other/notes.txt"), false));
+    }
+
+    #[test]
+    fn only_skips_git_folders_below_the_root() {
+        let fs = MemoryFs::new();
+        let rules = IgnoreRules::new(&fs, Path::new("/repo/.git/hooks"), ".toolignore");
+        assert!(!rules.is_ignored(Path::new("/repo/.git/hooks/pre-commit"), false));
+        assert!(rules.is_ignored(Path::new("/repo/.git/hooks/sub/.git"), true));
+    }
+}
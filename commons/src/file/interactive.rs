@@ -0,0 +1,133 @@
+use super::{model::FileOperationTask, traits::Relativize};
+use std::{
+    io::{self, BufRead, Write},
+    path::Path,
+};
+
+const HELP: &str =
+    "y - accept, n - skip, e - edit destination, a - accept all remaining, q - skip all remaining";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Answer {
+    Accept,
+    Skip,
+    Edit,
+    AcceptAll,
+    SkipAll,
+}
+
+fn parse_answer(line: &str) -> Option<Answer> {
+    match line.trim().to_lowercase().as_str() {
+        "y" | "yes" => Some(Answer::Accept),
+        "n" | "no" => Some(Answer::Skip),
+        "e" | "edit" => Some(Answer::Edit),
+        "a" | "all" => Some(Answer::AcceptAll),
+        "q" | "quit" => Some(Answer::SkipAll),
+        _ => None,
+    }
+}
+
+fn read_line(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+pub fn curate(
+    tasks: Vec<FileOperationTask>,
+    working_dir: &Path,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> io::Result<Vec<FileOperationTask>> {
+    let mut curated = vec![];
+    let mut i = 0;
+    while let Some(task) = tasks.get(i) {
+        let shown = task
+            .relativize(working_dir)
+            .unwrap_or_else(|_| task.clone());
+        write!(output, "{shown} [y,n,e,a,q,?] ")?;
+        output.flush()?;
+        // running out of input skips whatever was not answered yet
+        let Some(line) = read_line(input)? else {
+            writeln!(output)?;
+            break;
+        };
+        match parse_answer(&line) {
+            Some(Answer::Accept) => curated.push(task.clone()),
+            Some(Answer::Skip) => {}
+            Some(Answer::Edit) => {
+                write!(output, "New destination: ")?;
+                output.flush()?;
+                match read_line(input)? {
+                    Some(line) if !line.trim().is_empty() => {
+                        let to = working_dir.join(line.trim());
+                        curated.push(task.redirected(to));
+                    }
+                    _ => writeln!(output, "Empty destination, task skipped")?,
+                }
+            }
+            Some(Answer::AcceptAll) => {
+                curated.extend_from_slice(&tasks[i..]);
+                break;
+            }
+            Some(Answer::SkipAll) => break,
+            None => {
+                writeln!(output, "{HELP}")?;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    Ok(curated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn task(from: &str, to: &str) -> FileOperationTask {
+        FileOperationTask::new(PathBuf::from(from), PathBuf::from(to))
+    }
+
+    fn curate_with(answers: &str, tasks: Vec<FileOperationTask>) -> Vec<FileOperationTask> {
+        let mut output = vec![];
+        curate(
+            tasks,
+            Path::new("/work"),
+            &mut answers.as_bytes(),
+            &mut output,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn accepts_skips_and_edits_tasks() {
+        let tasks = vec![
+            task("/work/a/1.txt", "/work/1.txt"),
+            task("/work/a/2.txt", "/work/2.txt"),
+            task("/work/a/3.txt", "/work/3.txt"),
+            task("/work/a/4.txt", "/work/4.txt"),
+        ];
+        assert_eq!(
+            curate_with("y\nn\n?\ne\nb/three.txt\nq\n", tasks),
+            vec![
+                task("/work/a/1.txt", "/work/1.txt"),
+                task("/work/a/3.txt", "/work/b/three.txt"),
+            ]
+        );
+    }
+
+    #[test]
+    fn accepts_all_remaining_tasks() {
+        let tasks = vec![
+            task("/work/a/1.txt", "/work/1.txt"),
+            task("/work/a/2.txt", "/work/2.txt"),
+            task("/work/a/3.txt", "/work/3.txt"),
+        ];
+        assert_eq!(curate_with("n\na\n", tasks.clone()), tasks[1..].to_vec());
+        assert!(curate_with("", tasks).is_empty());
+    }
+}
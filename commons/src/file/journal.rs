@@ -0,0 +1,298 @@
+use super::{
+    config::CONFIG_DIR,
+    errors::JournalError,
+    filesystem::FileSystem,
+    model::{FailedFileOperation, FileOperationTask},
+    ordering::vacated_paths,
+    traits::{ExecuteTask, FileOperation, Instantiate, ToFailed},
+    transfer::move_path,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    env, io,
+    path::{self, Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The folder journals are saved to unless the executor is given another one,
+/// `$XDG_STATE_HOME/small_utils` or `~/.local/state/small_utils`.
+pub fn state_dir() -> Option<PathBuf> {
+    env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))
+        .map(|dir| dir.join(CONFIG_DIR))
+}
+
+pub fn timestamp() -> u64 {
+    to_millis(SystemTime::now())
+}
+
+fn to_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileFingerprint {
+    pub size: u64,
+    pub modified: u64,
+}
+
+impl FileFingerprint {
+    pub fn of(fs: &dyn FileSystem, path: &Path) -> Option<Self> {
+        let metadata = fs.metadata(path).ok()?;
+        Some(Self {
+            size: metadata.len,
+            modified: to_millis(metadata.modified),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    pub executed_at: u64,
+    pub fingerprint: Option<FileFingerprint>,
+}
+
+impl JournalEntry {
+    pub fn record(fs: &dyn FileSystem, task: &FileOperationTask) -> Self {
+        Self {
+            from: task.from.clone(),
+            to: task.to.clone(),
+            executed_at: timestamp(),
+            fingerprint: FileFingerprint::of(fs, &task.to),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Journal {
+    pub tool: String,
+    pub working_dir: PathBuf,
+    pub created_at: u64,
+    pub entries: Vec<JournalEntry>,
+}
+
+impl Journal {
+    pub fn new(tool: &str, working_dir: &Path, entries: Vec<JournalEntry>) -> Self {
+        Self {
+            tool: tool.to_string(),
+            // the journal is undone from anywhere, so relative paths would be lost
+            working_dir: path::absolute(working_dir).unwrap_or_else(|_| working_dir.to_path_buf()),
+            created_at: timestamp(),
+            entries,
+        }
+    }
+
+    pub fn load(fs: &dyn FileSystem, path: &Path) -> Result<Self, JournalError> {
+        let content = fs.read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Saves the journal to a new file in `dir`, creating the folder if needed.
+    pub fn save(&self, fs: &dyn FileSystem, dir: &Path) -> Result<PathBuf, JournalError> {
+        fs.create_dir_all(dir)?;
+        let path = dir.join(format!("{}-journal-{}.json", self.tool, self.created_at));
+        fs.write(&path, serde_json::to_string_pretty(self)?.as_bytes())?;
+        Ok(path)
+    }
+}
+
+pub struct Undo {
+    fs: Arc<dyn FileSystem>,
+    tasks: Vec<FileOperationTask>,
+    unrevertable: Vec<FailedFileOperation>,
+    failed_tasks: Vec<(usize, io::Error)>,
+}
+
+impl Instantiate<Journal> for Undo {
+    fn with_fs(fs: Arc<dyn FileSystem>, _working_dir: PathBuf, journal: Journal) -> Self {
+        let candidates = journal
+            .entries
+            .iter()
+            .rev()
+            .map(|entry| FileOperationTask::new(entry.to.clone(), entry.from.clone()))
+            .collect::<Vec<_>>();
+        let vacated = vacated_paths(&candidates);
+        let mut tasks = vec![];
+        let mut unrevertable = vec![];
+        for (task, entry) in candidates.into_iter().zip(journal.entries.iter().rev()) {
+            if !fs.exists(&task.from) {
+                unrevertable.push(task.to_failed("file has since been moved or deleted"));
+            } else if entry.fingerprint.is_some()
+                && FileFingerprint::of(fs.as_ref(), &task.from) != entry.fingerprint
+            {
+                unrevertable.push(task.to_failed("file has since been overwritten or modified"));
+            } else if fs.exists(&task.to) && !vacated.contains(&task.to) {
+                unrevertable.push(task.to_failed("original location is occupied by another file"));
+            } else {
+                tasks.push(task);
+            }
+        }
+        Self {
+            fs,
+            tasks,
+            unrevertable,
+            failed_tasks: vec![],
+        }
+    }
+}
+
+impl ExecuteTask for Undo {
+    fn execute_task(&self, task: &FileOperationTask) -> io::Result<u64> {
+        if let Some(parent) = task.to.parent() {
+            self.fs.create_dir_all(parent)?;
+        }
+        move_path(self.fs.as_ref(), &task.from, &task.to)
+    }
+}
+
+impl FileOperation<Journal> for Undo {
+    fn get_fs(&self) -> &dyn FileSystem {
+        self.fs.as_ref()
+    }
+
+    fn get_tasks(&self) -> Vec<FileOperationTask> {
+        self.tasks.clone()
+    }
+
+    fn get_tasks_mut(&mut self) -> &mut Vec<FileOperationTask> {
+        &mut self.tasks
+    }
+
+    fn get_skipped_tasks(&self) -> Vec<FailedFileOperation> {
+        self.unrevertable.clone()
+    }
+
+    fn get_failed_tasks(&self) -> &Vec<(usize, io::Error)> {
+        &self.failed_tasks
+    }
+
+    fn get_failed_tasks_mut(&mut self) -> &mut Vec<(usize, io::Error)> {
+        &mut self.failed_tasks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::filesystem::MemoryFs;
+
+    // the journal of moving every `(from, to)` pair, with the files already at `to`
+    fn journal(fs: &MemoryFs, moves: &[(&str, &str)]) -> Journal {
+        let entries = moves
+            .iter()
+            .map(|(from, to)| {
+                fs.add_file(to, to);
+                let task = FileOperationTask::new(PathBuf::from(from), PathBuf::from(to));
+                JournalEntry::record(fs, &task)
+            })
+            .collect();
+        Journal::new("test", Path::new("/work"), entries)
+    }
+
+    fn undo(fs: &Arc<MemoryFs>, journal: Journal) -> Undo {
+        Undo::with_fs(fs.clone(), PathBuf::from("/work"), journal)
+    }
+
+    fn reasons(undo: &Undo) -> Vec<(PathBuf, String)> {
+        undo.get_skipped_tasks()
+            .into_iter()
+            .map(|failed| (failed.file_path, failed.reason))
+            .collect()
+    }
+
+    #[test]
+    fn reverts_a_saved_journal() {
+        let fs = Arc::new(MemoryFs::new());
+        let saved = journal(
+            &fs,
+            &[
+                ("/work/a/one.txt", "/work/one.txt"),
+                ("/work/a/b/two.txt", "/work/two.txt"),
+            ],
+        );
+        let path = saved
+            .save(fs.as_ref(), Path::new("/state/small_utils"))
+            .unwrap();
+        assert_eq!(path.parent(), Some(Path::new("/state/small_utils")));
+        let loaded = Journal::load(fs.as_ref(), &path).unwrap();
+        assert_eq!(loaded.working_dir, PathBuf::from("/work"));
+
+        let mut undo = undo(&fs, loaded);
+        assert!(reasons(&undo).is_empty());
+        assert_eq!(undo.execute().failed, 0);
+        assert_eq!(
+            fs.read_to_string(Path::new("/work/a/one.txt")).unwrap(),
+            "/work/one.txt"
+        );
+        assert!(fs.exists(Path::new("/work/a/b/two.txt")));
+        assert!(!fs.exists(Path::new("/work/one.txt")));
+        assert!(!fs.exists(Path::new("/work/two.txt")));
+    }
+
+    #[test]
+    fn skips_files_that_have_been_moved_since() {
+        let fs = Arc::new(MemoryFs::new());
+        let journal = journal(&fs, &[("/work/a/one.txt", "/work/one.txt")]);
+        fs.remove_file(Path::new("/work/one.txt")).unwrap();
+        let undo = undo(&fs, journal);
+        assert!(undo.get_tasks().is_empty());
+        assert_eq!(
+            reasons(&undo),
+            vec![(
+                PathBuf::from("/work/one.txt"),
+                "file has since been moved or deleted".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn skips_files_that_have_been_modified_since() {
+        let fs = Arc::new(MemoryFs::new());
+        let journal = journal(&fs, &[("/work/a/one.txt", "/work/one.txt")]);
+        fs.add_file("/work/one.txt", "changed contents");
+        let undo = undo(&fs, journal);
+        assert!(undo.get_tasks().is_empty());
+        assert_eq!(
+            reasons(&undo),
+            vec![(
+                PathBuf::from("/work/one.txt"),
+                "file has since been overwritten or modified".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn skips_files_whose_original_location_is_occupied() {
+        let fs = Arc::new(MemoryFs::new());
+        let journal = journal(
+            &fs,
+            &[
+                ("/work/a/one.txt", "/work/one.txt"),
+                ("/work/b/two.txt", "/work/two.txt"),
+            ],
+        );
+        fs.add_file("/work/a/one.txt", "new");
+        let undo = undo(&fs, journal);
+        assert_eq!(
+            undo.get_tasks(),
+            vec![FileOperationTask::new(
+                PathBuf::from("/work/two.txt"),
+                PathBuf::from("/work/b/two.txt")
+            )]
+        );
+        assert_eq!(
+            reasons(&undo),
+            vec![(
+                PathBuf::from("/work/one.txt"),
+                "original location is occupied by another file".to_string()
+            )]
+        );
+    }
+}
@@ -1,35 +1,81 @@
+pub mod args;
+pub mod clash;
+pub mod config;
+pub mod conflict;
+pub mod editor;
 pub mod errors;
+pub mod executor;
+pub mod filesystem;
+pub mod filter;
+pub mod ignores;
+pub mod interactive;
+pub mod journal;
 pub mod model;
+pub mod ordering;
+pub mod output;
+pub mod pipeline;
+pub mod progress;
+pub mod symlink;
+#[cfg(test)]
+mod testing;
 pub mod traits;
+pub mod transfer;
 
-use pathdiff::diff_paths;
-use std::path::{Path, PathBuf};
-use walkdir::WalkDir;
+use errors::PathError;
+use filesystem::FileSystem;
+use ignores::IgnoreRules;
+use model::FailedFileOperation;
+use std::path::{Component, Path, PathBuf};
+use symlink::SymlinkPolicy;
+use traits::ToFailed;
 
-pub fn walkdir(root: &Path, depth: Option<usize>) -> Vec<PathBuf> {
-    let dir_iter = if let Some(depth) = depth {
-        WalkDir::new(root).max_depth(depth)
-    } else {
-        WalkDir::new(root)
-    };
-    dir_iter
+pub fn walkdir(fs: &dyn FileSystem, root: &Path, depth: Option<usize>) -> Vec<PathBuf> {
+    fs.walk(root, depth)
+}
+
+pub fn read_files(
+    fs: &dyn FileSystem,
+    root: &Path,
+    depth: Option<usize>,
+    symlinks: SymlinkPolicy,
+) -> (Vec<PathBuf>, Vec<PathError>) {
+    let (paths, errors) = fs.scan(root, depth, symlinks.follows());
+    let files = paths
         .into_iter()
-        .filter_map(|entry| entry.ok())
-        .map(|entry| entry.into_path())
-        .collect::<Vec<_>>()
+        .filter(|path| symlinks.selects_file(fs, path))
+        .collect::<Vec<_>>();
+    (files, errors)
 }
 
-pub fn read_files(root: &Path, depth: Option<usize>) -> Vec<PathBuf> {
-    walkdir(root, depth)
+pub fn read_files_ignoring(
+    fs: &dyn FileSystem,
+    root: &Path,
+    depth: Option<usize>,
+    tool_ignore_file: &str,
+    symlinks: SymlinkPolicy,
+) -> (Vec<PathBuf>, Vec<PathError>) {
+    // ignored folders are not walked, so neither their files nor their errors show up
+    let mut rules = IgnoreRules::new(fs, root, tool_ignore_file);
+    let (paths, errors) = fs.scan_filtered(root, depth, symlinks.follows(), &mut |path, is_dir| {
+        if rules.is_ignored(path, is_dir) {
+            return false;
+        }
+        if is_dir {
+            rules.add_dir(fs, path, tool_ignore_file);
+        }
+        true
+    });
+    let files = paths
         .into_iter()
-        .filter(|path| path.is_file())
-        .collect::<Vec<_>>()
+        .filter(|path| symlinks.selects_file(fs, path))
+        .collect::<Vec<_>>();
+    (files, errors)
 }
 
-pub fn read_dirs(root: &Path, depth: Option<usize>) -> Vec<PathBuf> {
-    walkdir(root, depth)
+pub fn read_dirs(fs: &dyn FileSystem, root: &Path, depth: Option<usize>) -> Vec<PathBuf> {
+    walkdir(fs, root, depth)
         .into_iter()
-        .filter(|path| path.is_dir())
+        .filter(|path| fs.is_dir(path))
         .collect::<Vec<_>>()
 }
 
@@ -47,26 +93,42 @@ pub fn filter_by_extension(files: Vec<PathBuf>, extensions: &[String]) -> Vec<Pa
         .collect::<Vec<_>>()
 }
 
-pub fn is_in_working_dir(working_dir: &PathBuf, file: &PathBuf) -> bool {
-    match diff_paths(file, working_dir) {
-        Some(diff) => diff.iter().count() != 1,
-        None => false,
+/// Resolves `.` and `..` without touching the file system, so a `..` after a
+/// link goes back to the folder containing the link.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
     }
+    normalized
 }
 
-#[cfg(windows)]
-pub fn is_hidden(file: &PathBuf) -> bool {
-    use std::{fs, os::windows::prelude::*};
-    let metadata =
-        fs::metadata(file).expect("file metadata could not be queried on 'file-hidden-check'");
-    let attributes = metadata.file_attributes();
-    (attributes & 0x2) > 0
+pub fn is_hidden(fs: &dyn FileSystem, file: &Path) -> Result<bool, PathError> {
+    fs.is_hidden(file).map_err(|e| PathError::from_io(file, e))
 }
 
-#[cfg(unix)]
-pub fn is_hidden(file: &Path) -> bool {
-    file.file_name()
-        .expect("file-name could not be queried on 'file-hidden-check'")
-        .to_string_lossy()
-        .starts_with('.')
+/// Drops hidden files, files whose visibility could not be determined are
+/// returned as skipped with the reason.
+pub fn without_hidden(
+    fs: &dyn FileSystem,
+    files: Vec<PathBuf>,
+) -> (Vec<PathBuf>, Vec<FailedFileOperation>) {
+    let mut skipped = vec![];
+    let visible = files
+        .into_iter()
+        .filter(|file| match is_hidden(fs, file) {
+            Ok(hidden) => !hidden,
+            Err(e) => {
+                skipped.push(file.to_failed(&e.to_string()));
+                false
+            }
+        })
+        .collect::<Vec<_>>();
+    (visible, skipped)
 }
@@ -1,21 +1,78 @@
 #![allow(clippy::derive_ord_xor_partial_ord)]
 
-use super::traits::{Relativize, ToFailed};
+use super::{
+    errors::PathError,
+    filesystem::FileSystem,
+    journal::JournalEntry,
+    output::{lossy_optional_path, lossy_path},
+    traits::{Relativize, ToFailed},
+};
 use pathdiff::diff_paths;
+use serde::Serialize;
 use std::{
     fmt::Display,
     path::{Path, PathBuf},
 };
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resolution {
+    Suffixed,
+    Overwrites,
+}
+
+impl Display for Resolution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Resolution::Suffixed => write!(f, "suffixed to avoid a conflict"),
+            Resolution::Overwrites => write!(f, "overwrites the existing file"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Serialize)]
 pub struct FileOperationTask {
+    #[serde(serialize_with = "lossy_path")]
     pub from: PathBuf,
+    #[serde(serialize_with = "lossy_path")]
     pub to: PathBuf,
+    pub resolution: Option<Resolution>,
+    /// Target of the link when `from` is a symbolic link
+    #[serde(serialize_with = "lossy_optional_path")]
+    pub link: Option<PathBuf>,
 }
 
 impl FileOperationTask {
     pub fn new(from: PathBuf, to: PathBuf) -> Self {
-        Self { from, to }
+        Self {
+            from,
+            to,
+            resolution: None,
+            link: None,
+        }
+    }
+
+    /// Records the target of `from` if it is a symbolic link.
+    pub fn linked(mut self, fs: &dyn FileSystem) -> Self {
+        self.link = fs.read_link(&self.from).ok();
+        self
+    }
+
+    /// The same task going to a different destination.
+    pub fn redirected(&self, to: PathBuf) -> Self {
+        Self {
+            to,
+            resolution: None,
+            ..self.clone()
+        }
+    }
+
+    pub fn overwrites(&self) -> bool {
+        self.resolution == Some(Resolution::Overwrites)
+    }
+
+    pub fn reversed(&self) -> Self {
+        Self::new(self.to.clone(), self.from.clone())
     }
 }
 
@@ -25,18 +82,32 @@ impl ToFailed for FileOperationTask {
     }
 }
 
+fn relative_path(path: &Path, working_dir: &Path) -> Result<PathBuf, PathError> {
+    diff_paths(path, working_dir).ok_or_else(|| PathError::NotRelativizable {
+        path: path.to_path_buf(),
+        base: working_dir.to_path_buf(),
+    })
+}
+
 impl Relativize for FileOperationTask {
-    fn relativize(&self, working_dir: &Path) -> Self {
+    fn relativize(&self, working_dir: &Path) -> Result<Self, PathError> {
         let mut task = self.clone();
-        task.from = diff_paths(&task.from, working_dir).unwrap();
-        task.to = diff_paths(&task.to, working_dir).unwrap();
-        task
+        task.from = relative_path(&task.from, working_dir)?;
+        task.to = relative_path(&task.to, working_dir)?;
+        Ok(task)
     }
 }
 
 impl Display for FileOperationTask {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{} -> {}", self.from.display(), self.to.display())
+        write!(f, "{} -> {}", self.from.display(), self.to.display())?;
+        if let Some(link) = &self.link {
+            write!(f, " (symlink to {})", link.display())?;
+        }
+        if let Some(resolution) = self.resolution {
+            write!(f, " ({resolution})")?;
+        }
+        Ok(())
     }
 }
 
@@ -50,8 +121,9 @@ impl Ord for FileOperationTask {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Serialize)]
 pub struct FailedFileOperation {
+    #[serde(serialize_with = "lossy_path")]
     pub file_path: PathBuf,
     pub reason: String,
 }
@@ -62,11 +134,17 @@ impl FailedFileOperation {
     }
 }
 
+impl From<&PathError> for FailedFileOperation {
+    fn from(e: &PathError) -> Self {
+        Self::new(e.path().to_path_buf(), e.reason())
+    }
+}
+
 impl Relativize for FailedFileOperation {
-    fn relativize(&self, working_dir: &Path) -> Self {
+    fn relativize(&self, working_dir: &Path) -> Result<Self, PathError> {
         let mut task = self.clone();
-        task.file_path = diff_paths(task.file_path, working_dir).unwrap();
-        task
+        task.file_path = relative_path(&task.file_path, working_dir)?;
+        Ok(task)
     }
 }
 
@@ -92,22 +170,34 @@ impl Ord for FailedFileOperation {
     }
 }
 
+#[derive(Debug, Clone)]
 pub struct FileOperationResult {
     pub successful: usize,
     pub failed: usize,
+    pub journal: Vec<JournalEntry>,
+    pub rolled_back: Vec<FileOperationTask>,
+    pub unrecoverable: Vec<FailedFileOperation>,
+    pub bytes_copied: u64,
 }
 
 impl FileOperationResult {
-    pub fn new(successful: usize, failed: usize) -> Self {
-        Self { successful, failed }
+    pub fn new(successful: usize, failed: usize, journal: Vec<JournalEntry>) -> Self {
+        Self {
+            successful,
+            failed,
+            journal,
+            rolled_back: vec![],
+            unrecoverable: vec![],
+            bytes_copied: 0,
+        }
     }
 }
 
 impl<T: Relativize> Relativize for Vec<T> {
-    fn relativize(&self, working_dir: &Path) -> Self {
+    fn relativize(&self, working_dir: &Path) -> Result<Self, PathError> {
         self.iter()
             .map(|task| task.relativize(working_dir))
-            .collect::<Vec<_>>()
+            .collect::<Result<Vec<_>, _>>()
     }
 }
 
@@ -0,0 +1,212 @@
+use super::{filesystem::FileSystem, model::FileOperationTask};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
+    process,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduledTask {
+    pub index: usize,
+    pub task: FileOperationTask,
+    pub blocked_by: Option<usize>,
+    pub is_final: bool,
+}
+
+impl ScheduledTask {
+    fn new(
+        index: usize,
+        task: FileOperationTask,
+        blocked_by: Option<usize>,
+        is_final: bool,
+    ) -> Self {
+        Self {
+            index,
+            task,
+            blocked_by,
+            is_final,
+        }
+    }
+}
+
+pub fn vacated_paths(tasks: &[FileOperationTask]) -> HashSet<PathBuf> {
+    tasks.iter().map(|task| task.from.clone()).collect()
+}
+
+pub fn temporary_path(fs: &dyn FileSystem, path: &Path, taken: &HashSet<PathBuf>) -> PathBuf {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut attempt = 0;
+    loop {
+        let candidate =
+            path.with_file_name(format!(".{file_name}.{}.{attempt}.tmp", process::id()));
+        if !taken.contains(&candidate) && !fs.exists(&candidate) {
+            return candidate;
+        }
+        attempt += 1;
+    }
+}
+
+pub fn schedule(fs: &dyn FileSystem, tasks: &[FileOperationTask]) -> Vec<ScheduledTask> {
+    let sources = tasks
+        .iter()
+        .enumerate()
+        .map(|(i, task)| (task.from.clone(), i))
+        .collect::<HashMap<_, _>>();
+    let destinations = tasks
+        .iter()
+        .enumerate()
+        .map(|(i, task)| (task.to.clone(), i))
+        .collect::<HashMap<_, _>>();
+    let mut blocked_by = vec![None; tasks.len()];
+    let mut dependents = vec![vec![]; tasks.len()];
+    for (i, task) in tasks.iter().enumerate() {
+        // a source that does not exist yet is brought in by the task moving its folder there
+        let brought_in_by = || {
+            task.from
+                .ancestors()
+                .skip(1)
+                .find_map(|dir| destinations.get(dir))
+                .filter(|_| !fs.exists(&task.from) && !fs.is_symlink(&task.from))
+        };
+        if let Some(&j) = brought_in_by().or_else(|| sources.get(&task.to)) {
+            if i != j {
+                blocked_by[i] = Some(j);
+                dependents[j].push(i);
+            }
+        }
+    }
+
+    let mut taken = tasks
+        .iter()
+        .flat_map(|task| [task.from.clone(), task.to.clone()])
+        .collect::<HashSet<_>>();
+    let mut current_from = tasks
+        .iter()
+        .map(|task| task.from.clone())
+        .collect::<Vec<_>>();
+    let mut waiting = blocked_by.iter().map(Option::is_some).collect::<Vec<_>>();
+    let mut done = vec![false; tasks.len()];
+    let mut ready = (0..tasks.len())
+        .filter(|&i| !waiting[i])
+        .collect::<VecDeque<_>>();
+    let mut release = |i: usize, ready: &mut VecDeque<usize>| {
+        for &dependent in &dependents[i] {
+            if waiting[dependent] {
+                waiting[dependent] = false;
+                ready.push_back(dependent);
+            }
+        }
+    };
+    let mut steps = vec![];
+    let mut finished = 0;
+
+    while finished < tasks.len() {
+        if let Some(i) = ready.pop_front() {
+            let task = FileOperationTask::new(current_from[i].clone(), tasks[i].to.clone());
+            steps.push(ScheduledTask::new(i, task, blocked_by[i], true));
+            done[i] = true;
+            finished += 1;
+            release(i, &mut ready);
+        } else {
+            // every remaining task waits on another one, so they form cycles:
+            // park one of them on a temporary name to let the rest proceed
+            let Some(i) = (0..tasks.len()).find(|&i| !done[i] && current_from[i] == tasks[i].from)
+            else {
+                break;
+            };
+            let temporary = temporary_path(fs, &tasks[i].from, &taken);
+            taken.insert(temporary.clone());
+            let task = FileOperationTask::new(tasks[i].from.clone(), temporary.clone());
+            steps.push(ScheduledTask::new(i, task, None, false));
+            current_from[i] = temporary;
+            release(i, &mut ready);
+        }
+    }
+    steps
+}
+
+pub fn batches(steps: Vec<ScheduledTask>) -> Vec<Vec<ScheduledTask>> {
+    // a step has to wait for the previous step of its own task and for the
+    // first step of the task it is blocked by, everything else can run at once
+    let mut last_level = HashMap::new();
+    let mut first_level = HashMap::new();
+    let mut batches: Vec<Vec<ScheduledTask>> = vec![];
+    for step in steps {
+        let level = [
+            last_level.get(&step.index),
+            step.blocked_by.and_then(|j| first_level.get(&j)),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|level| level + 1)
+        .max()
+        .unwrap_or(0);
+        first_level.entry(step.index).or_insert(level);
+        last_level.insert(step.index, level);
+        if batches.len() <= level {
+            batches.resize_with(level + 1, Vec::new);
+        }
+        batches[level].push(step);
+    }
+    batches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::filesystem::MemoryFs;
+
+    fn task(from: &str, to: &str) -> FileOperationTask {
+        FileOperationTask::new(PathBuf::from(from), PathBuf::from(to))
+    }
+
+    fn indices(batches: &[Vec<ScheduledTask>]) -> Vec<Vec<(usize, bool)>> {
+        batches
+            .iter()
+            .map(|batch| batch.iter().map(|s| (s.index, s.is_final)).collect())
+            .collect()
+    }
+
+    #[test]
+    fn batches_independent_tasks_together() {
+        let fs = MemoryFs::new();
+        let tasks = vec![task("/a", "/x"), task("/b", "/y"), task("/c", "/z")];
+        let batches = batches(schedule(&fs, &tasks));
+        assert_eq!(
+            indices(&batches),
+            vec![vec![(0, true), (1, true), (2, true)]]
+        );
+    }
+
+    #[test]
+    fn keeps_chains_and_cycles_ordered() {
+        let fs = MemoryFs::new();
+        let tasks = vec![
+            task("/a", "/b"),
+            task("/b", "/c"),
+            task("/x", "/y"),
+            task("/y", "/x"),
+        ];
+        let batches = batches(schedule(&fs, &tasks));
+        assert_eq!(
+            indices(&batches),
+            vec![
+                vec![(1, true), (2, false)],
+                vec![(0, true), (3, true)],
+                vec![(2, true)],
+            ]
+        );
+    }
+
+    #[test]
+    fn waits_for_the_folder_a_source_is_moved_in_with() {
+        let fs = MemoryFs::new();
+        fs.add_file("/w/a/b/c/file", "");
+        let tasks = vec![task("/w/b/c/file", "/w/b/file"), task("/w/a/b", "/w/b")];
+        let batches = batches(schedule(&fs, &tasks));
+        assert_eq!(indices(&batches), vec![vec![(1, true)], vec![(0, true)]]);
+    }
+}
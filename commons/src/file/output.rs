@@ -0,0 +1,366 @@
+use super::{
+    errors::FileError,
+    model::{FailedFileOperation, FileOperationTask},
+    progress::{NoProgress, Progress, ProgressBar},
+};
+use serde::{Serialize, Serializer};
+use std::{
+    fmt::Display,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+// serde refuses paths that are not valid UTF-8, the documents show them lossily instead
+pub(crate) fn lossy_path<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path.to_string_lossy())
+}
+
+pub(crate) fn lossy_optional_path<S: Serializer>(
+    path: &Option<PathBuf>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match path {
+        Some(path) => lossy_path(path, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+    Ndjson,
+}
+
+impl OutputFormat {
+    pub const VARIANTS: &'static [&'static str] = &["text", "json", "ndjson"];
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "ndjson" => Ok(Self::Ndjson),
+            _ => Err(format!("unknown output format '{s}'")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlanDocument {
+    pub tool: String,
+    pub operation: String,
+    #[serde(serialize_with = "lossy_path")]
+    pub working_dir: PathBuf,
+    pub tasks: Vec<FileOperationTask>,
+    pub skipped: Vec<FailedFileOperation>,
+    pub scan_errors: Vec<FailedFileOperation>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckErrorDocument {
+    pub kind: String,
+    pub message: String,
+    pub files: Vec<FailedFileOperation>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckDocument {
+    pub passed: bool,
+    pub error: Option<CheckErrorDocument>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ResultDocument {
+    pub successful: usize,
+    pub failed: usize,
+    pub failures: Vec<FailedFileOperation>,
+    pub rolled_back: Vec<FileOperationTask>,
+    pub unrecoverable: Vec<FailedFileOperation>,
+    pub bytes_copied: u64,
+    #[serde(serialize_with = "lossy_optional_path")]
+    pub journal: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorDocument {
+    pub kind: String,
+    pub message: String,
+    pub exit_code: i32,
+}
+
+impl From<&FileError> for ErrorDocument {
+    fn from(e: &FileError) -> Self {
+        Self {
+            kind: e.kind().to_string(),
+            message: e.to_string(),
+            exit_code: e.exit_code(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Plan(PlanDocument),
+    Check(CheckDocument),
+    Result(ResultDocument),
+    Error(ErrorDocument),
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct EventLine<'a> {
+    schema_version: u32,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Report {
+    pub schema_version: u32,
+    pub plan: Option<PlanDocument>,
+    pub check: Option<CheckDocument>,
+    pub result: Option<ResultDocument>,
+    pub errors: Vec<ErrorDocument>,
+}
+
+pub struct Output {
+    format: OutputFormat,
+    report: Report,
+}
+
+impl Output {
+    pub fn new(format: OutputFormat) -> Self {
+        Self {
+            format,
+            report: Report {
+                schema_version: SCHEMA_VERSION,
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn is_text(&self) -> bool {
+        self.format == OutputFormat::Text
+    }
+
+    /// Reports the progress of the execution for people only, machine output
+    /// gets no progress lines, not even on stderr.
+    pub fn progress(&self) -> Box<dyn Progress> {
+        if self.is_text() {
+            Box::new(ProgressBar::new())
+        } else {
+            Box::new(NoProgress)
+        }
+    }
+
+    pub fn text(&self, line: impl Display) {
+        if self.is_text() {
+            println!("{line}");
+        }
+    }
+
+    pub fn emit(&mut self, event: Event) -> Result<(), FileError> {
+        match self.format {
+            OutputFormat::Text => {}
+            OutputFormat::Ndjson => {
+                let line = EventLine {
+                    schema_version: SCHEMA_VERSION,
+                    event: &event,
+                };
+                write_json(|out| serde_json::to_writer(out, &line))?;
+            }
+            OutputFormat::Json => match event {
+                Event::Plan(plan) => self.report.plan = Some(plan),
+                Event::Check(check) => self.report.check = Some(check),
+                Event::Result(result) => self.report.result = Some(result),
+                Event::Error(error) => self.report.errors.push(error),
+            },
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<(), FileError> {
+        if self.format == OutputFormat::Json {
+            write_json(|out| serde_json::to_writer_pretty(out, &self.report))?;
+        }
+        Ok(())
+    }
+}
+
+// a closed stdout is reported like a document that could not be serialized
+fn write_json(
+    write: impl FnOnce(&mut io::StdoutLock) -> Result<(), serde_json::Error>,
+) -> Result<(), serde_json::Error> {
+    let mut out = io::stdout().lock();
+    write(&mut out)?;
+    writeln!(out).map_err(serde_json::Error::io)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    #[cfg(unix)]
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    fn keys(value: &Value) -> Vec<&str> {
+        let mut keys = value
+            .as_object()
+            .expect("documents are objects")
+            .keys()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys
+    }
+
+    fn line(event: &Event) -> Value {
+        serde_json::to_value(EventLine {
+            schema_version: SCHEMA_VERSION,
+            event,
+        })
+        .unwrap()
+    }
+
+    fn task() -> FileOperationTask {
+        FileOperationTask::new(PathBuf::from("a/x"), PathBuf::from("x"))
+    }
+
+    fn failed() -> FailedFileOperation {
+        FailedFileOperation::new(PathBuf::from("a/y"), "reason".to_string())
+    }
+
+    #[test]
+    fn events_keep_their_field_names() {
+        let plan = line(&Event::Plan(PlanDocument {
+            tool: "denest".to_string(),
+            operation: "move".to_string(),
+            working_dir: PathBuf::from("/work"),
+            tasks: vec![task()],
+            skipped: vec![failed()],
+            scan_errors: vec![],
+        }));
+        assert_eq!(
+            keys(&plan),
+            vec![
+                "event",
+                "operation",
+                "scan_errors",
+                "schema_version",
+                "skipped",
+                "tasks",
+                "tool",
+                "working_dir"
+            ]
+        );
+        assert_eq!(plan["event"], "plan");
+        assert_eq!(
+            keys(&plan["tasks"][0]),
+            vec!["from", "link", "resolution", "to"]
+        );
+        assert_eq!(keys(&plan["skipped"][0]), vec!["file_path", "reason"]);
+
+        let check = line(&Event::Check(CheckDocument {
+            passed: false,
+            error: Some(CheckErrorDocument {
+                kind: "clash".to_string(),
+                message: "message".to_string(),
+                files: vec![failed()],
+            }),
+        }));
+        assert_eq!(
+            keys(&check),
+            vec!["error", "event", "passed", "schema_version"]
+        );
+        assert_eq!(keys(&check["error"]), vec!["files", "kind", "message"]);
+
+        let result = line(&Event::Result(ResultDocument {
+            successful: 1,
+            failed: 0,
+            failures: vec![],
+            rolled_back: vec![],
+            unrecoverable: vec![],
+            bytes_copied: 0,
+            journal: None,
+        }));
+        assert_eq!(
+            keys(&result),
+            vec![
+                "bytes_copied",
+                "event",
+                "failed",
+                "failures",
+                "journal",
+                "rolled_back",
+                "schema_version",
+                "successful",
+                "unrecoverable"
+            ]
+        );
+
+        let error = line(&Event::Error(ErrorDocument::from(
+            &FileError::OperationsFailed(1),
+        )));
+        assert_eq!(
+            keys(&error),
+            vec!["event", "exit_code", "kind", "message", "schema_version"]
+        );
+    }
+
+    #[test]
+    fn report_keeps_its_field_names_and_empty_fields() {
+        let report = serde_json::to_value(Report {
+            schema_version: SCHEMA_VERSION,
+            plan: Some(PlanDocument {
+                tool: "denest".to_string(),
+                operation: "move".to_string(),
+                working_dir: PathBuf::from("/work"),
+                tasks: vec![task()],
+                skipped: vec![],
+                scan_errors: vec![],
+            }),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            keys(&report),
+            vec!["check", "errors", "plan", "result", "schema_version"]
+        );
+        assert_eq!(report["check"], Value::Null);
+        assert_eq!(report["plan"]["tasks"][0]["link"], Value::Null);
+        assert_eq!(report["plan"]["tasks"][0]["resolution"], Value::Null);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writes_names_that_are_not_valid_utf8() {
+        let name = PathBuf::from(OsStr::from_bytes(b"\xff"));
+        let event = Event::Plan(PlanDocument {
+            tool: "denest".to_string(),
+            operation: "move".to_string(),
+            working_dir: PathBuf::from("/work").join(&name),
+            tasks: vec![FileOperationTask::new(
+                PathBuf::from("a").join(&name),
+                name.clone(),
+            )],
+            skipped: vec![FailedFileOperation::new(name, "reason".to_string())],
+            scan_errors: vec![],
+        });
+        let plan = line(&event);
+        assert_eq!(plan["working_dir"], "/work/\u{fffd}");
+        assert_eq!(plan["tasks"][0]["from"], "a/\u{fffd}");
+        assert_eq!(plan["tasks"][0]["to"], "\u{fffd}");
+        assert_eq!(plan["skipped"][0]["file_path"], "\u{fffd}");
+        let mut out = Output::new(OutputFormat::Json);
+        assert!(out.emit(event).is_ok());
+        assert!(out.finish().is_ok());
+    }
+}
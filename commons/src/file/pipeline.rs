@@ -0,0 +1,430 @@
+use super::{
+    errors::{CheckBeforeError, ExecutionError, HookError, JournalError, PathError, PipelineError},
+    executor::{Execution, Executor},
+    filesystem::{FileMetadata, FileSystem, MemoryFs, RealFs},
+    journal::JournalEntry,
+    model::{FailedFileOperation, FileOperationTask},
+    traits::FileOperation,
+};
+use std::{
+    io,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+// Stages are planned against a snapshot and executed against the real tree,
+// so they get a file system that can be switched over in between.
+struct SwitchableFs {
+    current: RwLock<Arc<dyn FileSystem>>,
+}
+
+impl SwitchableFs {
+    fn new(fs: Arc<dyn FileSystem>) -> Self {
+        Self {
+            current: RwLock::new(fs),
+        }
+    }
+
+    fn switch(&self, fs: Arc<dyn FileSystem>) {
+        *self.current.write().expect("switchable fs lock poisoned") = fs;
+    }
+
+    fn current(&self) -> Arc<dyn FileSystem> {
+        self.current
+            .read()
+            .expect("switchable fs lock poisoned")
+            .clone()
+    }
+}
+
+impl FileSystem for SwitchableFs {
+    fn scan_filtered(
+        &self,
+        root: &Path,
+        depth: Option<usize>,
+        follow_links: bool,
+        keep: &mut dyn FnMut(&Path, bool) -> bool,
+    ) -> (Vec<PathBuf>, Vec<PathError>) {
+        self.current()
+            .scan_filtered(root, depth, follow_links, keep)
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        self.current().read_link(path)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FileMetadata> {
+        self.current().metadata(path)
+    }
+
+    fn is_hidden(&self, path: &Path) -> io::Result<bool> {
+        self.current().is_hidden(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.current().rename(from, to)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.current().create_dir_all(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        self.current().remove_dir(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.current().remove_file(path)
+    }
+
+    fn copy_file(&self, from: &Path, to: &Path) -> io::Result<u64> {
+        self.current().copy_file(from, to)
+    }
+
+    fn checksum(&self, path: &Path) -> io::Result<u32> {
+        self.current().checksum(path)
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        self.current().read_to_string(path)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.current().write(path, contents)
+    }
+
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+        self.current().symlink(target, link)
+    }
+}
+
+pub trait PipelineStage {
+    fn tasks(&self) -> Vec<FileOperationTask>;
+
+    fn skipped(&self) -> Vec<FailedFileOperation>;
+
+    fn check(&self) -> Option<CheckBeforeError>;
+
+    /// Runs the stage on the snapshot the way `execute` runs it on the disk,
+    /// returning the operations that failed.
+    fn simulate(&mut self) -> Result<Vec<FailedFileOperation>, HookError>;
+
+    fn execute(&mut self, executor: &Executor) -> Result<Execution, ExecutionError>;
+}
+
+struct Stage<T, C> {
+    operation: T,
+    config: PhantomData<fn() -> C>,
+}
+
+impl<T: FileOperation<C>, C> PipelineStage for Stage<T, C> {
+    fn tasks(&self) -> Vec<FileOperationTask> {
+        self.operation.get_tasks()
+    }
+
+    fn skipped(&self) -> Vec<FailedFileOperation> {
+        self.operation.get_skipped_tasks()
+    }
+
+    fn check(&self) -> Option<CheckBeforeError> {
+        self.operation.check_before_execution()
+    }
+
+    fn simulate(&mut self) -> Result<Vec<FailedFileOperation>, HookError> {
+        self.operation.before_execute()?;
+        self.operation.execute();
+        let _ = self.operation.after_execute();
+        let failures = self.operation.get_failed_operations();
+        self.operation.get_failed_tasks_mut().clear();
+        Ok(failures)
+    }
+
+    fn execute(&mut self, executor: &Executor) -> Result<Execution, ExecutionError> {
+        self.operation
+            .before_execute()
+            .map_err(ExecutionError::BeforeExecute)?;
+        let execution = executor.apply(&mut self.operation);
+        match self.operation.after_execute() {
+            Ok(_) => Ok(execution),
+            Err(e) => Err(ExecutionError::AfterExecute(e, Box::new(execution))),
+        }
+    }
+}
+
+type StageFactory = Box<dyn FnOnce(Arc<dyn FileSystem>, PathBuf) -> Box<dyn PipelineStage>>;
+
+pub struct Pipeline {
+    fs: Arc<dyn FileSystem>,
+    working_dir: PathBuf,
+    stages: Vec<(String, StageFactory)>,
+}
+
+impl Pipeline {
+    pub fn new(working_dir: PathBuf) -> Self {
+        Self::with_fs(Arc::new(RealFs), working_dir)
+    }
+
+    pub fn with_fs(fs: Arc<dyn FileSystem>, working_dir: PathBuf) -> Self {
+        Self {
+            fs,
+            working_dir,
+            stages: vec![],
+        }
+    }
+
+    pub fn stage<T, C>(mut self, name: &str, config: C) -> Self
+    where
+        T: FileOperation<C> + 'static,
+        C: 'static,
+    {
+        let factory: StageFactory = Box::new(move |fs, working_dir| {
+            Box::new(Stage::<T, C> {
+                operation: T::with_fs(fs, working_dir, config),
+                config: PhantomData,
+            })
+        });
+        self.stages.push((name.to_string(), factory));
+        self
+    }
+
+    /// Checks and runs every stage on a snapshot of the working dir, so later
+    /// stages are planned on the result of the earlier ones. The snapshot
+    /// lists the whole tree below the working dir once, regardless of the
+    /// ignore files and filters of the stages, file contents are only read
+    /// from the disk when a stage needs them.
+    pub fn plan(self) -> Result<PlannedPipeline, PipelineError> {
+        let snapshot = MemoryFs::snapshot(self.fs.clone(), &self.working_dir);
+        let fs = Arc::new(SwitchableFs::new(Arc::new(snapshot)));
+        let mut stages = vec![];
+        for (name, factory) in self.stages {
+            let mut stage = factory(fs.clone(), self.working_dir.clone());
+            if let Some(error) = stage.check() {
+                return Err(PipelineError::Check { stage: name, error });
+            }
+            let failures = match stage.simulate() {
+                Ok(failures) => failures,
+                Err(error) => return Err(PipelineError::BeforeExecute { stage: name, error }),
+            };
+            if !failures.is_empty() {
+                return Err(PipelineError::Simulation {
+                    stage: name,
+                    failures,
+                });
+            }
+            stages.push((name, stage));
+        }
+        fs.switch(self.fs.clone());
+        Ok(PlannedPipeline {
+            fs: self.fs,
+            working_dir: self.working_dir,
+            stages,
+        })
+    }
+}
+
+pub struct PlannedPipeline {
+    fs: Arc<dyn FileSystem>,
+    working_dir: PathBuf,
+    stages: Vec<(String, Box<dyn PipelineStage>)>,
+}
+
+#[derive(Debug)]
+pub struct StageReport {
+    pub name: String,
+    pub tasks: Vec<FileOperationTask>,
+    pub skipped: Vec<FailedFileOperation>,
+    // None when an earlier stage failed and this one was not run
+    pub execution: Option<Result<Execution, ExecutionError>>,
+}
+
+#[derive(Debug)]
+pub struct PipelineReport {
+    pub stages: Vec<StageReport>,
+    pub journal: Result<Option<PathBuf>, JournalError>,
+}
+
+impl PipelineReport {
+    fn executions(&self) -> impl Iterator<Item = &Execution> {
+        self.stages
+            .iter()
+            .filter_map(|stage| match &stage.execution {
+                Some(Ok(execution)) => Some(execution),
+                Some(Err(ExecutionError::AfterExecute(_, execution))) => Some(execution.as_ref()),
+                _ => None,
+            })
+    }
+
+    pub fn successful(&self) -> usize {
+        self.executions()
+            .map(|execution| execution.result.successful)
+            .sum()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.executions()
+            .map(|execution| execution.result.failed)
+            .sum()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.stages.iter().all(
+            |stage| matches!(&stage.execution, Some(Ok(execution)) if execution.result.failed == 0),
+        )
+    }
+}
+
+impl PlannedPipeline {
+    pub fn stages(&self) -> Vec<(&str, Vec<FileOperationTask>)> {
+        self.stages
+            .iter()
+            .map(|(name, stage)| (name.as_str(), stage.tasks()))
+            .collect()
+    }
+
+    pub fn execute(self, executor: &Executor) -> PipelineReport {
+        // stages are journaled together once everything ran
+        let stage_executor = executor.without_journal();
+        let mut reports = vec![];
+        let mut entries = vec![];
+        let mut stopped = false;
+        for (name, mut stage) in self.stages {
+            let execution = if stopped {
+                None
+            } else {
+                let execution = stage.execute(&stage_executor);
+                match &execution {
+                    Ok(execution) => {
+                        entries.extend(execution.result.journal.clone());
+                        stopped = execution.result.failed > 0;
+                    }
+                    Err(ExecutionError::AfterExecute(_, execution)) => {
+                        entries.extend(execution.result.journal.clone());
+                        stopped = true;
+                    }
+                    Err(_) => stopped = true,
+                }
+                Some(execution)
+            };
+            reports.push(StageReport {
+                name,
+                tasks: stage.tasks(),
+                skipped: stage.skipped(),
+                execution,
+            });
+        }
+        let entries = compose_entries(entries);
+        let journal = match executor.journal_tool() {
+            Some(tool) if !entries.is_empty() => executor
+                .save_journal(self.fs.as_ref(), tool, &self.working_dir, entries)
+                .map(Some),
+            _ => Ok(None),
+        };
+        PipelineReport {
+            stages: reports,
+            journal,
+        }
+    }
+}
+
+// a file moved by several stages is journaled as a single move from its
+// original place, so undoing the pipeline does not depend on stage order
+fn compose_entries(entries: Vec<JournalEntry>) -> Vec<JournalEntry> {
+    let mut composed: Vec<JournalEntry> = vec![];
+    for entry in entries {
+        match composed.iter_mut().find(|earlier| earlier.to == entry.from) {
+            Some(earlier) => {
+                earlier.to = entry.to;
+                earlier.executed_at = entry.executed_at;
+                earlier.fingerprint = entry.fingerprint;
+            }
+            None => composed.push(entry),
+        }
+    }
+    composed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::{
+        journal::Journal,
+        testing::{Mapper, Moves},
+    };
+
+    fn hoist(path: &Path) -> Option<PathBuf> {
+        let name = path.file_name()?;
+        (path.parent()? != Path::new("/work")).then(|| Path::new("/work").join(name))
+    }
+
+    fn prefix(path: &Path) -> Option<PathBuf> {
+        let name = path.file_name()?.to_string_lossy();
+        (path.parent()? == Path::new("/work") && !name.starts_with('.'))
+            .then(|| path.with_file_name(format!("p_{name}")))
+    }
+
+    fn pipeline(fs: &Arc<MemoryFs>) -> Pipeline {
+        Pipeline::with_fs(fs.clone(), PathBuf::from("/work"))
+            .stage::<Moves, Mapper>("hoist", hoist)
+            .stage::<Moves, Mapper>("prefix", prefix)
+    }
+
+    #[test]
+    fn plans_stages_against_the_simulated_tree() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/a/x.txt", "x");
+        fs.add_file("/work/y.txt", "y");
+        let planned = pipeline(&fs).plan().unwrap();
+        assert_eq!(fs.files().len(), 2);
+        assert_eq!(planned.stages()[1].1.len(), 2);
+
+        let executor = Executor::new()
+            .journal("test", Path::new("/work"))
+            .journal_dir(Path::new("/state"));
+        let report = planned.execute(&executor);
+        assert!(report.is_complete());
+        assert_eq!((report.successful(), report.failed()), (3, 0));
+        assert_eq!(fs.read_to_string(Path::new("/work/p_x.txt")).unwrap(), "x");
+        let path = report.journal.unwrap().unwrap();
+        assert!(path.starts_with("/state"));
+        let journal = Journal::load(fs.as_ref(), &path).unwrap();
+        let moves = journal
+            .entries
+            .iter()
+            .map(|entry| (entry.from.clone(), entry.to.clone()))
+            .collect::<Vec<_>>();
+        assert!(moves.contains(&(
+            PathBuf::from("/work/a/x.txt"),
+            PathBuf::from("/work/p_x.txt")
+        )));
+        assert_eq!(moves.len(), 2);
+    }
+
+    #[test]
+    fn rejects_pipelines_failing_in_a_later_stage() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/a/x.txt", "x");
+        fs.add_file("/work/p_x.txt", "taken");
+        let Err(PipelineError::Check { stage, .. }) = pipeline(&fs).plan() else {
+            panic!("the prefix stage should have failed its checks");
+        };
+        assert_eq!(stage, "prefix");
+        assert!(fs.exists(Path::new("/work/a/x.txt")));
+    }
+
+    #[test]
+    fn simulates_the_folders_stages_create_before_executing() {
+        fn archive(path: &Path) -> Option<PathBuf> {
+            Some(Path::new("/work/archive").join(path.file_name()?))
+        }
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/x.txt", "x");
+        let planned = Pipeline::with_fs(fs.clone(), PathBuf::from("/work"))
+            .stage::<Moves, Mapper>("archive", archive)
+            .plan()
+            .unwrap();
+        assert!(!fs.exists(Path::new("/work/archive")));
+        assert!(planned.execute(&Executor::new()).is_complete());
+        assert_eq!(fs.files(), vec![PathBuf::from("/work/archive/x.txt")]);
+    }
+}
@@ -0,0 +1,241 @@
+use std::{
+    io::{self, IsTerminal, Write},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+const BAR_WIDTH: usize = 30;
+const BAR_INTERVAL: Duration = Duration::from_millis(100);
+const LOG_INTERVAL: Duration = Duration::from_secs(2);
+
+pub trait Progress: Send + Sync {
+    fn start(&self, _total: usize) {}
+
+    fn task_done(&self, _failed: bool) {}
+
+    fn finish(&self) {}
+}
+
+pub struct NoProgress;
+
+impl Progress for NoProgress {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Bar,
+    Log,
+}
+
+#[derive(Debug, Clone)]
+struct State {
+    total: usize,
+    done: usize,
+    failed: usize,
+    started: Instant,
+    last_report: Option<Instant>,
+}
+
+impl State {
+    fn new(total: usize) -> Self {
+        Self {
+            total,
+            done: 0,
+            failed: 0,
+            started: Instant::now(),
+            last_report: None,
+        }
+    }
+
+    fn throughput(&self, elapsed: Duration) -> f64 {
+        let seconds = elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            self.done as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+
+    fn eta(&self, elapsed: Duration) -> Option<Duration> {
+        let throughput = self.throughput(elapsed);
+        if throughput > 0.0 {
+            let remaining = self.total.saturating_sub(self.done) as f64;
+            Some(Duration::from_secs_f64(remaining / throughput))
+        } else {
+            None
+        }
+    }
+
+    // log lines only start once a run takes longer than an interval, so short runs stay quiet
+    fn is_due(&self, mode: Mode, now: Instant) -> bool {
+        match mode {
+            Mode::Bar => self
+                .last_report
+                .is_none_or(|last| now.duration_since(last) >= BAR_INTERVAL),
+            Mode::Log => {
+                let last = self.last_report.unwrap_or(self.started);
+                now.duration_since(last) >= LOG_INTERVAL
+            }
+        }
+    }
+
+    fn summary(&self, elapsed: Duration) -> String {
+        let eta = self
+            .eta(elapsed)
+            .map(format_duration)
+            .unwrap_or_else(|| "--:--:--".to_string());
+        format!(
+            "{}/{} tasks, {} failed, {:.1} tasks/s, ETA {eta}",
+            self.done,
+            self.total,
+            self.failed,
+            self.throughput(elapsed)
+        )
+    }
+
+    fn bar(&self, elapsed: Duration) -> String {
+        let filled = (BAR_WIDTH * self.done)
+            .checked_div(self.total)
+            .unwrap_or(BAR_WIDTH)
+            .min(BAR_WIDTH);
+        format!(
+            "[{}{}] {}",
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            self.summary(elapsed)
+        )
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Reports progress on stderr, as a redrawn bar when stdout is a terminal and
+/// as periodic log lines otherwise, runs shorter than the log interval are
+/// not logged at all.
+pub struct ProgressBar {
+    mode: Mode,
+    state: Mutex<State>,
+}
+
+impl ProgressBar {
+    pub fn new() -> Self {
+        let mode = if io::stdout().is_terminal() {
+            Mode::Bar
+        } else {
+            Mode::Log
+        };
+        Self {
+            mode,
+            state: Mutex::new(State::new(0)),
+        }
+    }
+
+    fn report(&self, state: &mut State, force: bool) {
+        let now = Instant::now();
+        if !force && !state.is_due(self.mode, now) {
+            return;
+        }
+        state.last_report = Some(now);
+        let elapsed = now.duration_since(state.started);
+        let mut stderr = io::stderr().lock();
+        let _ = match self.mode {
+            Mode::Bar => write!(stderr, "\r{}", state.bar(elapsed)),
+            Mode::Log => writeln!(stderr, "Progress: {}", state.summary(elapsed)),
+        };
+        let _ = stderr.flush();
+    }
+}
+
+impl Default for ProgressBar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Progress for ProgressBar {
+    fn start(&self, total: usize) {
+        let mut state = self.state.lock().expect("progress lock poisoned");
+        *state = State::new(total);
+        if self.mode == Mode::Bar {
+            self.report(&mut state, true);
+        }
+    }
+
+    fn task_done(&self, failed: bool) {
+        let mut state = self.state.lock().expect("progress lock poisoned");
+        state.done += 1;
+        if failed {
+            state.failed += 1;
+        }
+        self.report(&mut state, false);
+    }
+
+    fn finish(&self) {
+        let mut state = self.state.lock().expect("progress lock poisoned");
+        match self.mode {
+            Mode::Bar => {
+                self.report(&mut state, true);
+                eprintln!();
+            }
+            // the final line closes the ones logged before
+            Mode::Log if state.last_report.is_some() => self.report(&mut state, true),
+            Mode::Log => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(total: usize, done: usize, failed: usize) -> State {
+        State {
+            done,
+            failed,
+            ..State::new(total)
+        }
+    }
+
+    #[test]
+    fn estimates_remaining_time_from_throughput() {
+        let state = state(100, 25, 0);
+        let elapsed = Duration::from_secs(10);
+        assert_eq!(state.throughput(elapsed), 2.5);
+        assert_eq!(state.eta(elapsed), Some(Duration::from_secs(30)));
+        assert_eq!(State::new(100).eta(elapsed), None);
+    }
+
+    #[test]
+    fn renders_bar_with_counts() {
+        let state = state(10, 5, 1);
+        assert_eq!(
+            state.bar(Duration::from_secs(5)),
+            format!(
+                "[{}{}] 5/10 tasks, 1 failed, 1.0 tasks/s, ETA 00:00:05",
+                "#".repeat(15),
+                "-".repeat(15)
+            )
+        );
+        assert_eq!(format_duration(Duration::from_secs(3723)), "01:02:03");
+    }
+
+    #[test]
+    fn logs_only_runs_longer_than_the_interval() {
+        let mut state = state(10, 1, 0);
+        let started = state.started;
+        assert!(state.is_due(Mode::Bar, started));
+        assert!(!state.is_due(Mode::Log, started + Duration::from_secs(1)));
+        assert!(state.is_due(Mode::Log, started + LOG_INTERVAL));
+
+        state.last_report = Some(started + LOG_INTERVAL);
+        assert!(!state.is_due(Mode::Log, started + LOG_INTERVAL + Duration::from_secs(1)));
+        assert!(state.is_due(Mode::Log, started + LOG_INTERVAL * 2));
+    }
+}
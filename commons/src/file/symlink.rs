@@ -0,0 +1,108 @@
+use super::filesystem::FileSystem;
+use std::{fmt::Display, path::Path, str::FromStr};
+
+/// How symbolic links found while scanning are treated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymlinkPolicy {
+    /// Leave links and whatever they point to alone
+    Skip,
+    /// Operate on the links themselves, without descending into linked folders
+    #[default]
+    Link,
+    /// Descend into linked folders, stopping at links back to an ancestor
+    Follow,
+}
+
+impl SymlinkPolicy {
+    pub const VARIANTS: &'static [&'static str] = &["skip", "link", "follow"];
+
+    pub fn follows(&self) -> bool {
+        *self == Self::Follow
+    }
+
+    /// Whether a scanned `path` should be treated as a file to operate on.
+    pub fn selects_file(&self, fs: &dyn FileSystem, path: &Path) -> bool {
+        match self {
+            Self::Skip => !fs.is_symlink(path) && fs.is_file(path),
+            Self::Link => fs.is_symlink(path) || fs.is_file(path),
+            Self::Follow => fs.is_file(path),
+        }
+    }
+}
+
+impl FromStr for SymlinkPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(Self::Skip),
+            "link" => Ok(Self::Link),
+            "follow" => Ok(Self::Follow),
+            _ => Err(format!("unknown symlink policy '{s}'")),
+        }
+    }
+}
+
+impl Display for SymlinkPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Skip => "skip",
+            Self::Link => "link",
+            Self::Follow => "follow",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::{filesystem::MemoryFs, read_files};
+    use std::path::PathBuf;
+
+    fn fs() -> MemoryFs {
+        let fs = MemoryFs::new();
+        fs.add_file("/work/a/one.txt", "");
+        fs.add_file("/outside/two.txt", "");
+        fs.add_link("/work/a/one-link.txt", "one.txt");
+        fs.add_link("/work/b", "/outside");
+        fs.add_link("/work/a/loop", "..");
+        fs
+    }
+
+    fn files(policy: SymlinkPolicy) -> (Vec<PathBuf>, usize) {
+        let (files, errors) = read_files(&fs(), Path::new("/work"), None, policy);
+        (files, errors.len())
+    }
+
+    #[test]
+    fn policies_select_links_or_their_targets() {
+        assert_eq!(
+            files(SymlinkPolicy::Skip),
+            (vec![PathBuf::from("/work/a/one.txt")], 0)
+        );
+        assert_eq!(
+            files(SymlinkPolicy::Link),
+            (
+                vec![
+                    PathBuf::from("/work/a/loop"),
+                    PathBuf::from("/work/a/one-link.txt"),
+                    PathBuf::from("/work/a/one.txt"),
+                    PathBuf::from("/work/b"),
+                ],
+                0
+            )
+        );
+        assert_eq!(
+            files(SymlinkPolicy::Follow),
+            (
+                vec![
+                    PathBuf::from("/work/a/one-link.txt"),
+                    PathBuf::from("/work/a/one.txt"),
+                    PathBuf::from("/work/b/two.txt"),
+                ],
+                1
+            )
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use super::{
+    errors::{CheckBeforeError, HookError},
+    filesystem::FileSystem,
+    model::{FailedFileOperation, FileOperationTask},
+    read_files,
+    symlink::SymlinkPolicy,
+    traits::{ExecuteTask, FileOperation, Instantiate, ToFailed},
+    transfer::move_path,
+};
+use std::{
+    collections::BTreeSet,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+pub type Mapper = fn(&Path) -> Option<PathBuf>;
+
+/// Moves the files below the working dir the mapper gives a destination, or
+/// the moves it was built with.
+pub struct Moves {
+    pub fs: Arc<dyn FileSystem>,
+    pub tasks: Vec<FileOperationTask>,
+    pub failed_tasks: Vec<(usize, io::Error)>,
+}
+
+impl Moves {
+    pub fn new(fs: Arc<dyn FileSystem>, moves: &[(&str, &str)]) -> Self {
+        Self {
+            fs,
+            tasks: moves
+                .iter()
+                .map(|(from, to)| FileOperationTask::new(PathBuf::from(from), PathBuf::from(to)))
+                .collect(),
+            failed_tasks: vec![],
+        }
+    }
+}
+
+impl Instantiate<Mapper> for Moves {
+    fn with_fs(fs: Arc<dyn FileSystem>, working_dir: PathBuf, mapper: Mapper) -> Self {
+        let tasks = read_files(fs.as_ref(), &working_dir, None, SymlinkPolicy::Link)
+            .0
+            .into_iter()
+            .filter_map(|from| mapper(&from).map(|to| FileOperationTask::new(from, to)))
+            .collect();
+        Self {
+            fs,
+            tasks,
+            failed_tasks: vec![],
+        }
+    }
+}
+
+impl ExecuteTask for Moves {
+    fn check_before_execution(&self) -> Option<CheckBeforeError> {
+        let targets = self
+            .tasks
+            .iter()
+            .filter(|task| self.fs.exists(&task.to))
+            .map(|task| task.to_failed("would overwrite an existing file"))
+            .collect();
+        CheckBeforeError::first(vec![], targets, vec![])
+    }
+
+    fn before_execute(&self) -> Result<bool, HookError> {
+        let dirs = self
+            .tasks
+            .iter()
+            .filter_map(|task| task.to.parent())
+            .filter(|dir| !self.fs.is_dir(dir))
+            .collect::<BTreeSet<_>>();
+        for dir in &dirs {
+            self.fs.create_dir_all(dir)?;
+        }
+        Ok(!dirs.is_empty())
+    }
+
+    fn execute_task(&self, task: &FileOperationTask) -> io::Result<u64> {
+        move_path(self.fs.as_ref(), &task.from, &task.to)
+    }
+}
+
+impl FileOperation<Mapper> for Moves {
+    fn get_fs(&self) -> &dyn FileSystem {
+        self.fs.as_ref()
+    }
+
+    fn get_tasks(&self) -> Vec<FileOperationTask> {
+        self.tasks.clone()
+    }
+
+    fn get_tasks_mut(&mut self) -> &mut Vec<FileOperationTask> {
+        &mut self.tasks
+    }
+
+    fn get_skipped_tasks(&self) -> Vec<FailedFileOperation> {
+        vec![]
+    }
+
+    fn get_failed_tasks(&self) -> &Vec<(usize, io::Error)> {
+        &self.failed_tasks
+    }
+
+    fn get_failed_tasks_mut(&mut self) -> &mut Vec<(usize, io::Error)> {
+        &mut self.failed_tasks
+    }
+}
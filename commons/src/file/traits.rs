@@ -1,17 +1,35 @@
-#![allow(clippy::result_unit_err)]
-
 use super::{
-    errors::CheckBeforeError,
+    args::CommonArgs,
+    config::from_layered_args,
+    editor::edit_plan,
+    errors::{CheckBeforeError, ExecutionError, FileError, HookError, PartialMove, PathError},
+    executor::Executor,
+    filesystem::{FileSystem, RealFs},
+    interactive::curate,
+    journal::{Journal, JournalEntry, Undo},
     model::{FailedFileOperation, FileOperationResult, FileOperationTask},
+    ordering::{batches, schedule, ScheduledTask},
+    output::{
+        CheckDocument, CheckErrorDocument, ErrorDocument, Event, Output, PlanDocument,
+        ResultDocument,
+    },
+    progress::{NoProgress, Progress},
+};
+use rayon::{
+    iter::{IntoParallelRefIterator, ParallelIterator},
+    ThreadPool, ThreadPoolBuilder,
 };
 use std::{
+    collections::{HashMap, HashSet},
     env, io,
     path::{Path, PathBuf},
+    process,
+    sync::Arc,
 };
 use structopt::StructOpt;
 
-pub trait Relativize {
-    fn relativize(&self, working_dir: &Path) -> Self;
+pub trait Relativize: Sized {
+    fn relativize(&self, working_dir: &Path) -> Result<Self, PathError>;
 }
 
 pub trait ToFileTask: IntoIterator + Sized {
@@ -28,14 +46,18 @@ pub trait ToFileTask: IntoIterator + Sized {
     }
 }
 
-impl ToFileTask for Vec<PathBuf> {}
+impl<T> ToFileTask for Vec<T> {}
 
 pub trait ToFailed {
     fn to_failed(&self, reason: &str) -> FailedFileOperation;
 }
 
-pub trait Instantiate<C> {
-    fn new(working_dir: PathBuf, args: C) -> Self;
+pub trait Instantiate<C>: Sized {
+    fn new(working_dir: PathBuf, args: C) -> Self {
+        Self::with_fs(Arc::new(RealFs), working_dir, args)
+    }
+
+    fn with_fs(fs: Arc<dyn FileSystem>, working_dir: PathBuf, args: C) -> Self;
 }
 
 pub trait ExecuteTask {
@@ -43,20 +65,34 @@ pub trait ExecuteTask {
         None
     }
 
-    fn before_execute(&self) -> Result<bool, ()> {
+    fn before_execute(&self) -> Result<bool, HookError> {
         Ok(false)
     }
 
-    fn execute_task(task: &FileOperationTask) -> io::Result<()>;
+    fn execute_task(&self, task: &FileOperationTask) -> io::Result<u64>;
 
-    fn after_execute(&self) -> Result<bool, ()> {
+    fn revert_task(&self, task: &FileOperationTask) -> io::Result<u64> {
+        self.execute_task(&task.reversed())
+    }
+
+    fn after_execute(&self) -> Result<bool, HookError> {
         Ok(false)
     }
 }
 
-pub trait FileOperation<C>: Instantiate<C> + ExecuteTask {
+pub trait FileOperation<C>: Instantiate<C> + ExecuteTask + Sync {
+    fn get_fs(&self) -> &dyn FileSystem;
+
     fn get_tasks(&self) -> Vec<FileOperationTask>;
 
+    fn get_tasks_mut(&mut self) -> &mut Vec<FileOperationTask>;
+
+    fn get_skipped_tasks(&self) -> Vec<FailedFileOperation>;
+
+    fn get_scan_errors(&self) -> Vec<FailedFileOperation> {
+        vec![]
+    }
+
     fn get_failed_tasks(&self) -> &Vec<(usize, io::Error)>;
 
     fn get_failed_tasks_mut(&mut self) -> &mut Vec<(usize, io::Error)>;
@@ -75,22 +111,219 @@ pub trait FileOperation<C>: Instantiate<C> + ExecuteTask {
     }
 
     fn execute(&mut self) -> FileOperationResult {
-        self.get_tasks().iter().enumerate().for_each(|(i, task)| {
-            if let Err(e) = Self::execute_task(task) {
-                self.get_failed_tasks_mut().push((i, e))
+        self.execute_with(1, &NoProgress)
+    }
+
+    fn execute_with(&mut self, jobs: usize, progress: &dyn Progress) -> FileOperationResult {
+        let tasks = self.get_tasks();
+        progress.start(tasks.len());
+        let pool = thread_pool(jobs);
+        let mut journal = vec![];
+        let mut parked = HashMap::new();
+        let mut failed = HashSet::new();
+        let mut bytes_copied = 0;
+        for batch in plan_batches(self.get_fs(), &tasks, jobs) {
+            let batch = batch
+                .into_iter()
+                .filter(|step| !failed.contains(&step.index))
+                .collect::<Vec<_>>();
+            let results = run_batch(&*self, pool.as_ref(), &batch, &tasks, &failed);
+            for (step, result) in batch.into_iter().zip(results) {
+                match result {
+                    Ok(bytes) if step.is_final => {
+                        bytes_copied += bytes;
+                        parked.remove(&step.index);
+                        journal.push(JournalEntry::record(self.get_fs(), &tasks[step.index]));
+                        progress.task_done(false);
+                    }
+                    Ok(bytes) => {
+                        bytes_copied += bytes;
+                        parked.insert(step.index, JournalEntry::record(self.get_fs(), &step.task));
+                    }
+                    Err(e) => {
+                        let partial = PartialMove::of(&e).is_some();
+                        // the complete copy of a partly removed folder can be undone from the journal
+                        if partial {
+                            journal.push(JournalEntry::record(self.get_fs(), &step.task));
+                        }
+                        let original = &tasks[step.index].from;
+                        let e = if step.is_final && &step.task.from != original && !partial {
+                            match self.unpark(&step.task.from, original, e) {
+                                Ok(e) => {
+                                    parked.remove(&step.index);
+                                    e
+                                }
+                                Err(e) => e,
+                            }
+                        } else {
+                            e
+                        };
+                        failed.insert(step.index);
+                        self.get_failed_tasks_mut().push((step.index, e));
+                        progress.task_done(true);
+                    }
+                }
             }
-        });
-        FileOperationResult::new(
-            self.get_tasks().len() - self.get_failed_tasks().len(),
+        }
+        progress.finish();
+        journal.extend(parked.into_values());
+        let mut result = FileOperationResult::new(
+            tasks.len() - self.get_failed_tasks().len(),
             self.get_failed_tasks().len(),
-        )
+            journal,
+        );
+        result.bytes_copied = bytes_copied;
+        result
+    }
+
+    /// Moves a file parked on a temporary name back to where it was after its
+    /// final step failed, the error names the temporary path if that fails too.
+    fn unpark(
+        &self,
+        temporary: &Path,
+        original: &Path,
+        error: io::Error,
+    ) -> Result<io::Error, io::Error> {
+        let task = FileOperationTask::new(temporary.to_path_buf(), original.to_path_buf());
+        if !self.get_fs().exists(original) && self.execute_task(&task).is_ok() {
+            Ok(error)
+        } else {
+            Err(io::Error::new(
+                error.kind(),
+                format!("{error}, the file was left at {}", temporary.display()),
+            ))
+        }
+    }
+
+    fn execute_transactional(&mut self) -> FileOperationResult {
+        self.execute_transactional_with(1, &NoProgress)
+    }
+
+    fn execute_transactional_with(
+        &mut self,
+        jobs: usize,
+        progress: &dyn Progress,
+    ) -> FileOperationResult {
+        let tasks = self.get_tasks();
+        progress.start(tasks.len());
+        let pool = thread_pool(jobs);
+        let mut applied = vec![];
+        let mut partial = vec![];
+        let mut bytes_copied = 0;
+        for batch in plan_batches(self.get_fs(), &tasks, jobs) {
+            let results = run_batch(&*self, pool.as_ref(), &batch, &tasks, &HashSet::new());
+            for (step, result) in batch.into_iter().zip(results) {
+                match result {
+                    Ok(bytes) => {
+                        bytes_copied += bytes;
+                        if step.is_final {
+                            progress.task_done(false);
+                        }
+                        applied.push(step);
+                    }
+                    Err(e) => {
+                        if let Some(partial_move) = PartialMove::of(&e) {
+                            partial.push((step.task.clone(), partial_move.to_string()));
+                        }
+                        self.get_failed_tasks_mut().push((step.index, e));
+                        progress.task_done(true);
+                    }
+                }
+            }
+            if !self.get_failed_tasks().is_empty() {
+                break;
+            }
+        }
+        progress.finish();
+        if self.get_failed_tasks().is_empty() {
+            let journal = tasks
+                .iter()
+                .map(|task| JournalEntry::record(self.get_fs(), task))
+                .collect();
+            let mut result = FileOperationResult::new(tasks.len(), 0, journal);
+            result.bytes_copied = bytes_copied;
+            return result;
+        }
+
+        let mut rolled_back = vec![];
+        let mut unrecoverable = vec![];
+        let mut journal = vec![];
+        for (task, reason) in partial {
+            unrecoverable.push(task.to.to_failed(&reason));
+            journal.push(JournalEntry::record(self.get_fs(), &task));
+        }
+        for step in applied.into_iter().rev() {
+            match self.revert_task(&step.task) {
+                Ok(_) if step.is_final => rolled_back.push(tasks[step.index].clone()),
+                Ok(_) => {}
+                Err(e) => {
+                    unrecoverable.push(step.task.to.to_failed(&format!(
+                        "could not be moved back to {}: {e}",
+                        step.task.from.display()
+                    )));
+                    journal.push(JournalEntry::record(self.get_fs(), &step.task));
+                }
+            }
+        }
+        journal.reverse();
+        let mut result = FileOperationResult::new(0, self.get_failed_tasks().len(), journal);
+        result.rolled_back = rolled_back;
+        result.unrecoverable = unrecoverable;
+        result
     }
 }
 
+fn thread_pool(jobs: usize) -> Option<ThreadPool> {
+    if jobs > 1 {
+        ThreadPoolBuilder::new().num_threads(jobs).build().ok()
+    } else {
+        None
+    }
+}
+
+fn plan_batches(
+    fs: &dyn FileSystem,
+    tasks: &[FileOperationTask],
+    jobs: usize,
+) -> Vec<Vec<ScheduledTask>> {
+    let steps = schedule(fs, tasks);
+    if jobs > 1 {
+        batches(steps)
+    } else {
+        steps.into_iter().map(|step| vec![step]).collect()
+    }
+}
+
+fn run_batch<E: ExecuteTask + Sync>(
+    operation: &E,
+    pool: Option<&ThreadPool>,
+    batch: &[ScheduledTask],
+    tasks: &[FileOperationTask],
+    failed: &HashSet<usize>,
+) -> Vec<io::Result<u64>> {
+    let run = |step: &ScheduledTask| match step.blocked_by.filter(|j| failed.contains(j)) {
+        Some(j) => Err(blocked_error(&tasks[j])),
+        None => operation.execute_task(&step.task),
+    };
+    match pool {
+        Some(pool) => pool.install(|| batch.par_iter().map(run).collect()),
+        None => batch.iter().map(run).collect(),
+    }
+}
+
+fn blocked_error(blocking_task: &FileOperationTask) -> io::Error {
+    io::Error::other(format!(
+        "skipped, because {} could not be moved out of the way",
+        blocking_task.from.display()
+    ))
+}
+
 pub trait InputArgs {
     fn working_dir(&self) -> Option<PathBuf>;
 
     fn do_exec(&self) -> bool;
+
+    fn common(&self) -> &CommonArgs;
 }
 
 pub trait Runnable<A, C, T>
@@ -103,101 +336,423 @@ where
     fn verb() -> String;
 
     fn run() {
-        let args = A::from_args();
+        let args = match from_layered_args::<A>() {
+            Ok(args) => args,
+            Err(e) => {
+                let e = FileError::from(e);
+                eprintln!("error: {e}");
+                process::exit(e.exit_code());
+            }
+        };
+        let tool = A::clap().get_name().to_string();
+        let common = args.common().clone();
+        let flush = args.do_exec();
+        let mut out = Output::new(common.format);
+        let outcome = match &common.undo {
+            Some(journal_path) => Self::undo(&tool, journal_path, &common, flush, &mut out),
+            None => Self::plan_and_process(args, &tool, &common, flush, &mut out),
+        };
+        if let Err(e) = &outcome {
+            // the failure is reported on stderr below even if it cannot be written here
+            let _ = out.emit(Event::Error(ErrorDocument::from(e)));
+        }
+        let finished = out.finish();
+        if let Err(e) = outcome.and(finished) {
+            eprintln!("error: {e}");
+            process::exit(e.exit_code());
+        }
+    }
+
+    fn plan_and_process(
+        args: A,
+        tool: &str,
+        common: &CommonArgs,
+        flush: bool,
+        out: &mut Output,
+    ) -> Result<(), FileError> {
         let working_dir = match args.working_dir() {
             Some(dir) => dir,
-            None => env::current_dir().expect("failed to get working directory"),
+            None => env::current_dir().map_err(|e| PathError::from_io(Path::new("."), e))?,
         };
-        let flush = args.do_exec();
-        let mut file_operation = T::new(working_dir.clone(), args.into());
+        let file_operation = T::new(working_dir.clone(), args.into());
+
+        out.text(Self::name());
+        Self::process(
+            file_operation,
+            tool,
+            &working_dir,
+            &Self::verb(),
+            common,
+            flush,
+            out,
+        )
+    }
+
+    fn undo(
+        tool: &str,
+        journal_path: &Path,
+        common: &CommonArgs,
+        flush: bool,
+        out: &mut Output,
+    ) -> Result<(), FileError> {
+        out.text(Self::name());
+        let journal = Journal::load(&RealFs, journal_path).inspect_err(|e| {
+            out.text(format!("\nFailed to load journal: {e}"));
+        })?;
+        let working_dir = journal.working_dir.clone();
+        out.text(format!(
+            "\nUndoing {} operations of '{}' in {}",
+            journal.entries.len(),
+            journal.tool,
+            working_dir.display()
+        ));
+        let undo = Undo::new(working_dir.clone(), journal);
+        Self::process(
+            undo,
+            &format!("{tool}-undo"),
+            &working_dir,
+            "restore",
+            common,
+            flush,
+            out,
+        )
+    }
 
-        println!("{}", Self::name());
-        let operation_name = Self::verb();
+    fn process<C2, O>(
+        mut file_operation: O,
+        tool: &str,
+        working_dir: &Path,
+        operation_name: &str,
+        common: &CommonArgs,
+        flush: bool,
+        out: &mut Output,
+    ) -> Result<(), FileError>
+    where
+        O: FileOperation<C2>,
+    {
+        let tasks = file_operation.get_tasks().relativize(working_dir)?;
+        let skipped = file_operation.get_skipped_tasks().relativize(working_dir)?;
+        let scan_errors = file_operation.get_scan_errors().relativize(working_dir)?;
+        out.emit(Event::Plan(PlanDocument {
+            tool: tool.to_string(),
+            operation: operation_name.to_string(),
+            working_dir: working_dir.to_path_buf(),
+            tasks: tasks.clone(),
+            skipped: skipped.clone(),
+            scan_errors: scan_errors.clone(),
+        }))?;
+        if tasks.is_empty() && skipped.is_empty() && scan_errors.is_empty() {
+            out.text(format!(
+                "\nNo files found to be {operation_name}d with these arguments!\n"
+            ));
+            return Ok(());
+        }
+
+        if !tasks.is_empty() {
+            out.text(format!("\nFile {operation_name}s to be made:"));
+            tasks.iter().for_each(|task| {
+                out.text(task);
+            });
+        }
+
+        if !skipped.is_empty() {
+            out.text(format!("\nFile {operation_name}s skipped:"));
+            skipped.iter().for_each(|skipped_task| {
+                out.text(skipped_task);
+            });
+        }
+
+        if !scan_errors.is_empty() {
+            out.text("\nSkipped during scan:");
+            scan_errors.iter().for_each(|scan_error| {
+                out.text(scan_error);
+            });
+            if common.strict {
+                out.text("\nThe scan was incomplete, nothing is executed in strict mode");
+                return Err(FileError::IncompleteScan(scan_errors.len()));
+            }
+        }
 
-        let tasks = file_operation.get_tasks().relativize(&working_dir);
         if tasks.is_empty() {
-            println!("\nNo files found to be {operation_name}d with these arguments!\n");
-            return;
+            out.text(format!("\nNo files left to be {operation_name}d!\n"));
+            return Ok(());
         }
 
-        println!("\nFile {operation_name}s to be made:");
-        tasks.iter().for_each(|task| {
-            println!("{task}");
-        });
+        if common.edit {
+            match edit_plan(tool, &file_operation.get_tasks(), working_dir) {
+                Ok(edited) => *file_operation.get_tasks_mut() = edited,
+                Err(e) => {
+                    out.text(format!("\nFailed to edit the plan: {e}"));
+                    return Err(e.into());
+                }
+            }
+        }
 
-        println!("\nRunning checks before execution...");
-        if let Some(e) = file_operation.check_before_execution() {
-            println!("Failed to execute {operation_name}s:");
-            match &e {
-                CheckBeforeError::FilesWouldOverwrite(files) => {
-                    println!("{e}\n");
-                    files.iter().for_each(|task| {
-                        println!("{}", task.relativize(&working_dir));
-                    });
+        if common.interactive {
+            eprintln!("\nSelect the {operation_name}s to execute:");
+            match curate(
+                file_operation.get_tasks(),
+                working_dir,
+                &mut io::stdin().lock(),
+                &mut io::stderr(),
+            ) {
+                Ok(curated) => *file_operation.get_tasks_mut() = curated,
+                Err(e) => {
+                    out.text(format!("Failed to read the selection: {e}"));
+                    return Err(FileError::Selection(e));
                 }
             }
-            return;
+        }
+
+        if common.edit || common.interactive {
+            let tasks = file_operation.get_tasks().relativize(working_dir)?;
+            out.emit(Event::Plan(PlanDocument {
+                tool: tool.to_string(),
+                operation: operation_name.to_string(),
+                working_dir: working_dir.to_path_buf(),
+                tasks: tasks.clone(),
+                skipped: skipped.clone(),
+                scan_errors: scan_errors.clone(),
+            }))?;
+            if tasks.is_empty() {
+                out.text(format!("\nNo files selected to be {operation_name}d!\n"));
+                return Ok(());
+            }
+            out.text(format!("\nSelected file {operation_name}s:"));
+            tasks.iter().for_each(|task| {
+                out.text(task);
+            });
+        }
+
+        out.text("\nRunning checks before execution...");
+        if let Some(e) = file_operation.check_before_execution() {
+            out.text(format!("Failed to execute {operation_name}s:"));
+            let files = e.files().to_vec().relativize(working_dir)?;
+            out.text(format!("{e}\n"));
+            files.iter().for_each(|task| {
+                out.text(task);
+            });
+            out.emit(Event::Check(CheckDocument {
+                passed: false,
+                error: Some(CheckErrorDocument {
+                    kind: e.kind().to_string(),
+                    message: e.to_string(),
+                    files,
+                }),
+            }))?;
+            return Err(ExecutionError::Check(e).into());
         } else {
-            println!("All checks passed!");
+            out.text("All checks passed!");
+            out.emit(Event::Check(CheckDocument {
+                passed: true,
+                error: None,
+            }))?;
         }
 
         if flush {
-            println!("\nBefore execution running...");
+            out.text("\nBefore execution running...");
             match file_operation.before_execute() {
                 Ok(ran) => {
                     if ran {
-                        println!("Before execution ran successfully!");
+                        out.text("Before execution ran successfully!");
                     } else {
-                        println!("No before exectution ran!");
+                        out.text("No before exectution ran!");
                     }
                 }
-                Err(_) => {
-                    println!("Before execution failed!");
-                    return;
+                Err(e) => {
+                    out.text(format!("Before execution failed: {e}"));
+                    return Err(ExecutionError::BeforeExecute(e).into());
                 }
             };
 
-            println!("\nExecuting {operation_name}s...");
-            let FileOperationResult { successful, failed } = file_operation.execute();
+            out.text(format!("\nExecuting {operation_name}s..."));
+            let progress = out.progress();
+            let executor = Executor::new()
+                .atomic(common.atomic)
+                .jobs(common.jobs)
+                .progress(progress.as_ref())
+                .journal(tool, working_dir);
+            let execution = executor.apply(&mut file_operation);
+            let FileOperationResult {
+                successful,
+                failed,
+                bytes_copied,
+                ..
+            } = execution.result;
+            let failures = execution.failures.clone().relativize(working_dir)?;
+            let rolled_back = execution.result.rolled_back.clone();
+            let unrecoverable = execution
+                .result
+                .unrecoverable
+                .clone()
+                .relativize(working_dir)?;
             if failed == 0 {
-                println!("Execution successful, {successful} files {operation_name}d!");
-            } else if successful == 0 {
-                println!("All {failed} {operation_name}s failed:");
-                file_operation
-                    .get_failed_operations()
-                    .relativize(&working_dir)
-                    .iter()
-                    .for_each(|failed_task| {
-                        println!("{failed_task}");
+                out.text(format!(
+                    "Execution successful, {successful} files {operation_name}d!"
+                ));
+            } else if common.atomic {
+                out.text(format!(
+                    "Execution failed, rolling back all {operation_name}s:"
+                ));
+                failures.iter().for_each(|failed_task| {
+                    out.text(failed_task);
+                });
+                out.text(format!(
+                    "\n{} {operation_name}s rolled back successfully!",
+                    rolled_back.len()
+                ));
+                if !unrecoverable.is_empty() {
+                    out.text(format!(
+                        "{} {operation_name}s could not be rolled back:",
+                        unrecoverable.len()
+                    ));
+                    unrecoverable.iter().for_each(|failed_task| {
+                        out.text(failed_task);
                     });
+                }
+            } else if successful == 0 {
+                out.text(format!("All {failed} {operation_name}s failed:"));
+                failures.iter().for_each(|failed_task| {
+                    out.text(failed_task);
+                });
             } else {
-                println!(
+                out.text(format!(
                     "{successful} {operation_name}s are successful, but {failed} {operation_name}s failed:"
-                );
-                file_operation
-                    .get_failed_operations()
-                    .relativize(&working_dir)
-                    .iter()
-                    .for_each(|failed_task| {
-                        println!("{failed_task}");
-                    });
+                ));
+                failures.iter().for_each(|failed_task| {
+                    out.text(failed_task);
+                });
             }
 
-            println!("\nAfter execution running...");
-            match file_operation.after_execute() {
+            if bytes_copied > 0 {
+                out.text(format!(
+                    "{bytes_copied} bytes were copied and verified across filesystems"
+                ));
+            }
+
+            let journal_path = match &execution.journal {
+                Ok(Some(path)) => {
+                    out.text(format!(
+                        "Journal saved to {}, pass it to --undo to revert",
+                        path.display()
+                    ));
+                    Some(path.clone())
+                }
+                Ok(None) => None,
+                Err(e) => {
+                    out.text(format!("Failed to save journal: {e}"));
+                    None
+                }
+            };
+            out.emit(Event::Result(ResultDocument {
+                successful,
+                failed,
+                failures,
+                rolled_back: rolled_back.relativize(working_dir)?,
+                unrecoverable,
+                bytes_copied,
+                journal: journal_path,
+            }))?;
+
+            out.text("\nAfter execution running...");
+            let after = match file_operation.after_execute() {
                 Ok(ran) => {
                     if ran {
-                        println!("After execution ran successfully!");
+                        out.text("After execution ran successfully!");
                     } else {
-                        println!("No after exectution ran!");
+                        out.text("No after exectution ran!");
                     }
+                    Ok(())
                 }
-                Err(_) => {
-                    println!("After execution failed!");
+                Err(e) => {
+                    out.text(format!("After execution failed: {e}"));
+                    Err(e)
                 }
             };
+            // the first failure decides the exit code, later ones are only reported
+            if failed > 0 {
+                if let Err(e) = after {
+                    let e = ExecutionError::AfterExecute(e, Box::new(execution)).into();
+                    out.emit(Event::Error(ErrorDocument::from(&e)))?;
+                }
+                return Err(FileError::OperationsFailed(failed));
+            }
+            if let Err(e) = execution.journal {
+                return Err(e.into());
+            }
+            after.map_err(|e| ExecutionError::AfterExecute(e, Box::new(execution)).into())
         } else {
-            println!("Run with -d flag to execute {operation_name}s\n");
+            out.text(format!("Run with -d flag to execute {operation_name}s\n"));
+            Ok(())
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::{filesystem::MemoryFs, testing::Moves};
+
+    fn moves(fs: &Arc<MemoryFs>, moves: &[(&str, &str)]) -> Moves {
+        Moves::new(fs.clone(), moves)
+    }
+
+    #[test]
+    fn moves_parked_files_back_when_their_cycle_breaks() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/w/a", "a");
+        fs.add_file("/w/b", "b");
+        fs.fail_on("/w/b");
+        let mut swap = moves(&fs, &[("/w/a", "/w/b"), ("/w/b", "/w/a")]);
+        let result = swap.execute();
+        assert_eq!(result.failed, 2);
+        assert!(result.journal.is_empty());
+        assert_eq!(fs.read_to_string(Path::new("/w/a")).unwrap(), "a");
+        assert_eq!(
+            fs.files(),
+            vec![PathBuf::from("/w/a"), PathBuf::from("/w/b")]
+        );
+    }
+
+    #[test]
+    fn reports_where_a_parked_file_was_left() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/w/a", "a");
+        fs.add_file("/w/b", "b");
+        fs.add_file("/w/c", "c");
+        fs.fail_on("/w/b");
+        let mut cycle = moves(&fs, &[("/w/a", "/w/b"), ("/w/b", "/w/c"), ("/w/c", "/w/a")]);
+        let result = cycle.execute();
+        assert_eq!(result.failed, 2);
+        let left_at = fs
+            .files()
+            .into_iter()
+            .find(|file| file.to_string_lossy().ends_with(".tmp"))
+            .expect("the parked file stays parked");
+        let (_, e) = cycle
+            .get_failed_tasks()
+            .iter()
+            .find(|(i, _)| *i == 0)
+            .unwrap();
+        assert!(e
+            .to_string()
+            .ends_with(&format!("the file was left at {}", left_at.display())));
+        assert!(result.journal.iter().any(|entry| entry.to == left_at));
+    }
+
+    #[test]
+    fn journals_folders_that_were_only_partly_moved() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/w/dir/a", "a");
+        fs.add_mount("/mnt");
+        fs.fail_on("/w/dir/a");
+        let mut moves = moves(&fs, &[("/w/dir", "/mnt/dir")]);
+        let result = moves.execute();
+        assert_eq!(result.failed, 1);
+        assert_eq!(result.journal.len(), 1);
+        assert_eq!(result.journal[0].from, PathBuf::from("/w/dir"));
+        assert_eq!(result.journal[0].to, PathBuf::from("/mnt/dir"));
+        let (_, e) = &moves.get_failed_tasks()[0];
+        assert!(PartialMove::of(e).is_some());
+    }
+}
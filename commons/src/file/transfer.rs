@@ -0,0 +1,260 @@
+use super::{errors::PartialMove, filesystem::FileSystem, ordering::temporary_path};
+use std::{
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+};
+
+pub fn is_cross_device(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::CrossesDevices
+}
+
+pub fn move_path(fs: &dyn FileSystem, from: &Path, to: &Path) -> io::Result<u64> {
+    match fs.rename(from, to) {
+        Ok(_) => Ok(0),
+        // copying would replace a link with the contents of its target
+        Err(e) if is_cross_device(&e) && !fs.is_symlink(from) => {
+            if fs.is_dir(from) {
+                move_dir_across_devices(fs, from, to)
+            } else {
+                move_file_across_devices(fs, from, to)
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn copy_verified(fs: &dyn FileSystem, from: &Path, to: &Path) -> io::Result<u64> {
+    let bytes = fs.copy_file(from, to)?;
+    let verified = fs.checksum(from)? == fs.checksum(to)?;
+    if !verified {
+        let _ = fs.remove_file(to);
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("checksum of the copy of {} does not match", from.display()),
+        ));
+    }
+    Ok(bytes)
+}
+
+fn move_file_across_devices(fs: &dyn FileSystem, from: &Path, to: &Path) -> io::Result<u64> {
+    if !fs.exists(to) && !fs.is_symlink(to) {
+        let bytes = copy_verified(fs, from, to)?;
+        fs.remove_file(from)?;
+        return Ok(bytes);
+    }
+    // a file that is overwritten is only replaced by a complete copy
+    let copy = temporary_path(fs, to, &HashSet::new());
+    let bytes = copy_verified(fs, from, &copy)?;
+    if let Err(e) = fs.rename(&copy, to) {
+        let _ = fs.remove_file(&copy);
+        return Err(e);
+    }
+    fs.remove_file(from)?;
+    Ok(bytes)
+}
+
+fn move_dir_across_devices(fs: &dyn FileSystem, from: &Path, to: &Path) -> io::Result<u64> {
+    // a rename would not merge into an existing folder either
+    if fs.exists(to) || fs.is_symlink(to) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists already", to.display()),
+        ));
+    }
+    let entries = fs.walk(from, None);
+    let mut created = vec![];
+    let bytes = match copy_tree(fs, from, to, &entries, &mut created) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            // a half copied tree is removed again, the source is still complete
+            remove_entries(fs, &created);
+            return Err(e);
+        }
+    };
+    // only delete the source once the whole tree has been copied and verified
+    for entry in entries.iter().rev() {
+        let removed = if is_real_dir(fs, entry) {
+            fs.remove_dir(entry)
+        } else {
+            fs.remove_file(entry)
+        };
+        if let Err(source) = removed {
+            let partial = PartialMove {
+                from: from.to_path_buf(),
+                to: to.to_path_buf(),
+                source,
+            };
+            return Err(partial.into_io());
+        }
+    }
+    Ok(bytes)
+}
+
+fn copy_tree(
+    fs: &dyn FileSystem,
+    from: &Path,
+    to: &Path,
+    entries: &[PathBuf],
+    created: &mut Vec<PathBuf>,
+) -> io::Result<u64> {
+    let mut bytes = 0;
+    for entry in entries {
+        let Ok(relative) = entry.strip_prefix(from) else {
+            continue;
+        };
+        let target = to.join(relative);
+        if let Ok(link) = fs.read_link(entry) {
+            // links are recreated as they are, copying them would follow them
+            fs.symlink(&link, &target)?;
+        } else if fs.is_dir(entry) {
+            fs.create_dir_all(&target)?;
+        } else {
+            bytes += copy_verified(fs, entry, &target)?;
+        }
+        created.push(target);
+    }
+    Ok(bytes)
+}
+
+fn remove_entries(fs: &dyn FileSystem, entries: &[PathBuf]) {
+    for entry in entries.iter().rev() {
+        let _ = if is_real_dir(fs, entry) {
+            fs.remove_dir(entry)
+        } else {
+            fs.remove_file(entry)
+        };
+    }
+}
+
+fn is_real_dir(fs: &dyn FileSystem, path: &Path) -> bool {
+    fs.is_dir(path) && !fs.is_symlink(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::filesystem::MemoryFs;
+
+    #[test]
+    fn renames_on_the_same_device_without_copying() {
+        let fs = MemoryFs::new();
+        fs.add_file("/work/a.txt", "hello");
+        let bytes = move_path(&fs, Path::new("/work/a.txt"), Path::new("/work/b.txt")).unwrap();
+        assert_eq!(bytes, 0);
+        assert_eq!(
+            fs.read_to_string(Path::new("/work/b.txt")).unwrap(),
+            "hello"
+        );
+    }
+
+    #[test]
+    fn copies_files_across_devices_and_removes_the_source() {
+        let fs = MemoryFs::new();
+        fs.add_file("/work/a.txt", "hello");
+        fs.add_mount("/mnt");
+        let bytes = move_path(&fs, Path::new("/work/a.txt"), Path::new("/mnt/a.txt")).unwrap();
+        assert_eq!(bytes, 5);
+        assert!(!fs.exists(Path::new("/work/a.txt")));
+        assert_eq!(fs.read_to_string(Path::new("/mnt/a.txt")).unwrap(), "hello");
+    }
+
+    #[test]
+    fn copies_directories_across_devices() {
+        let fs = MemoryFs::new();
+        fs.add_file("/work/dir/a.txt", "a");
+        fs.add_file("/work/dir/nested/b.txt", "bb");
+        fs.add_mount("/mnt");
+        let bytes = move_path(&fs, Path::new("/work/dir"), Path::new("/mnt/dir")).unwrap();
+        assert_eq!(bytes, 3);
+        assert!(!fs.exists(Path::new("/work/dir")));
+        assert_eq!(
+            fs.read_to_string(Path::new("/mnt/dir/nested/b.txt"))
+                .unwrap(),
+            "bb"
+        );
+    }
+
+    #[test]
+    fn keeps_links_when_moving_directories_across_devices() {
+        let fs = MemoryFs::new();
+        fs.add_file("/work/dir/a.txt", "a");
+        fs.add_file("/work/other/b.txt", "b");
+        fs.add_link("/work/dir/file", "a.txt");
+        fs.add_link("/work/dir/folder", "/work/other");
+        fs.add_link("/work/dir/dangling", "missing.txt");
+        fs.add_mount("/mnt");
+        let bytes = move_path(&fs, Path::new("/work/dir"), Path::new("/mnt/dir")).unwrap();
+        assert_eq!(bytes, 1);
+        assert!(!fs.exists(Path::new("/work/dir")));
+        assert!(fs.exists(Path::new("/work/other/b.txt")));
+        for (link, target) in [
+            ("/mnt/dir/file", "a.txt"),
+            ("/mnt/dir/folder", "/work/other"),
+            ("/mnt/dir/dangling", "missing.txt"),
+        ] {
+            assert_eq!(
+                fs.read_link(Path::new(link)).unwrap(),
+                PathBuf::from(target)
+            );
+        }
+    }
+
+    #[test]
+    fn removes_the_partial_copy_when_moving_a_directory_fails() {
+        let fs = MemoryFs::new();
+        fs.add_file("/work/dir/a.txt", "a");
+        fs.add_file("/work/dir/nested/b.txt", "b");
+        fs.add_mount("/mnt");
+        fs.fail_on("/mnt/dir/nested/b.txt");
+        assert!(move_path(&fs, Path::new("/work/dir"), Path::new("/mnt/dir")).is_err());
+        assert!(!fs.exists(Path::new("/mnt/dir")));
+        assert!(fs.exists(Path::new("/work/dir/a.txt")));
+        assert!(fs.exists(Path::new("/work/dir/nested/b.txt")));
+    }
+
+    #[test]
+    fn replaces_an_existing_file_across_devices() {
+        let fs = MemoryFs::new();
+        fs.add_file("/work/a.txt", "new");
+        fs.add_file("/mnt/a.txt", "old");
+        fs.add_mount("/mnt");
+        move_path(&fs, Path::new("/work/a.txt"), Path::new("/mnt/a.txt")).unwrap();
+        assert_eq!(fs.read_to_string(Path::new("/mnt/a.txt")).unwrap(), "new");
+        assert_eq!(fs.files(), vec![PathBuf::from("/mnt/a.txt")]);
+    }
+
+    #[test]
+    fn does_not_move_directories_into_existing_ones_across_devices() {
+        let fs = MemoryFs::new();
+        fs.add_file("/work/dir/a.txt", "new");
+        fs.add_file("/mnt/dir/a.txt", "old");
+        fs.add_mount("/mnt");
+        let e = move_path(&fs, Path::new("/work/dir"), Path::new("/mnt/dir")).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(
+            fs.read_to_string(Path::new("/mnt/dir/a.txt")).unwrap(),
+            "old"
+        );
+        assert_eq!(
+            fs.read_to_string(Path::new("/work/dir/a.txt")).unwrap(),
+            "new"
+        );
+    }
+
+    #[test]
+    fn reports_a_partial_move_when_the_source_cannot_be_removed() {
+        let fs = MemoryFs::new();
+        fs.add_file("/work/dir/a.txt", "a");
+        fs.add_file("/work/dir/b.txt", "b");
+        fs.add_mount("/mnt");
+        fs.fail_on("/work/dir/a.txt");
+        let e = move_path(&fs, Path::new("/work/dir"), Path::new("/mnt/dir")).unwrap_err();
+        let partial = PartialMove::of(&e).expect("the copy is complete");
+        assert_eq!(partial.from, PathBuf::from("/work/dir"));
+        assert_eq!(partial.to, PathBuf::from("/mnt/dir"));
+        assert!(fs.exists(Path::new("/mnt/dir/a.txt")));
+        assert!(fs.exists(Path::new("/mnt/dir/b.txt")));
+        assert!(fs.exists(Path::new("/work/dir/a.txt")));
+    }
+}
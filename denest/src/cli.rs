@@ -1,5 +1,9 @@
-use crate::logic::{Config, Denest};
-use commons::file::traits::{InputArgs, Runnable};
+use commons::file::{
+    args::CommonArgs,
+    filter::PathPattern,
+    traits::{InputArgs, Runnable},
+};
+use denest::{Config, Denest, FolderSelection, Naming, NamingMode, Separator};
 use std::path::PathBuf;
 use structopt::StructOpt;
 
@@ -17,14 +21,66 @@ pub struct Args {
     /// Depth of the recursive search
     #[structopt(long)]
     pub depth: Option<u8>,
+    /// Only move files at least this deep, files in the working directory are 1 deep
+    #[structopt(long)]
+    pub min_depth: Option<u8>,
+    /// Only hoist files out of folders matching these globs, patterns without a '/' match the
+    /// folder name
+    #[structopt(long)]
+    pub folders: Vec<PathPattern>,
+    /// Only hoist files out of folders holding at most this many files, or matching --folders
+    #[structopt(long)]
+    pub max_files: Option<usize>,
+    /// Collapse folders whose only entry is another folder instead of moving files, until no
+    /// such folder is left. Links are moved as they are, or left in place with --symlinks skip
+    #[structopt(
+        long,
+        conflicts_with_all = &[
+            "extensions",
+            "depth",
+            "min-depth",
+            "folders",
+            "max-files",
+            "naming",
+            "separator",
+            "parent-levels",
+            "keep-levels",
+            "target",
+            "include",
+            "exclude",
+            "match",
+        ]
+    )]
+    pub wrappers: bool,
     /// Cleanup the empty folders after
     #[structopt(short, long)]
     pub cleanup: bool,
+    /// Name hoisted files after the folders they were nested in, before or after their name,
+    /// 'keep' by default
+    #[structopt(long, possible_values = NamingMode::VARIANTS)]
+    pub naming: Option<NamingMode>,
+    /// Separator between the folder names and the file name when naming by folders, ' - ' by
+    /// default, it cannot contain '/', nor '\' on Windows
+    #[structopt(long)]
+    pub separator: Option<Separator>,
+    /// Number of closest parent folders kept in the name when naming by folders
+    #[structopt(long)]
+    pub parent_levels: Option<usize>,
+    /// Keep this many folder levels below the working directory and only flatten the
+    /// folders below them
+    #[structopt(long)]
+    pub keep_levels: Option<usize>,
+    /// Move the files into this folder instead of the working directory
+    #[structopt(long)]
+    pub target: Option<PathBuf>,
+    /// Also move the files directly in the working directory into --target
+    #[structopt(long, requires = "target")]
+    pub include_root: bool,
     /// Specify the working directory
     #[structopt(long)]
     pub working_dir: Option<PathBuf>,
-    // pub savepoint: bool, // save a csv with information to restore the moved files
-    // pub load_savepoint: bool // restore the moved files on failure
+    #[structopt(flatten)]
+    pub common: CommonArgs,
 }
 
 impl InputArgs for Args {
@@ -35,11 +91,31 @@ impl InputArgs for Args {
     fn do_exec(&self) -> bool {
         self.do_moves
     }
+
+    fn common(&self) -> &CommonArgs {
+        &self.common
+    }
 }
 
 impl From<Args> for Config {
     fn from(args: Args) -> Self {
         Config::new(args.extensions, args.depth, args.cleanup)
+            .on_conflict(args.common.on_conflict)
+            .filter(args.common.path_filter())
+            .respect_ignore(!args.common.no_ignore)
+            .symlinks(args.common.symlinks)
+            .clash_rules(args.common.clash_rules())
+            .naming(Naming::new(
+                args.naming.unwrap_or_default(),
+                args.separator.unwrap_or_default(),
+                args.parent_levels,
+            ))
+            .target(args.target)
+            .include_root(args.include_root)
+            .keep_levels(args.keep_levels.unwrap_or_default())
+            .min_depth(args.min_depth)
+            .folders(FolderSelection::new(args.folders, args.max_files))
+            .collapse_wrappers(args.wrappers)
     }
 }
 
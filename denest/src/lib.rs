@@ -0,0 +1,16 @@
+pub mod logic;
+pub mod naming;
+pub mod selection;
+pub mod wrappers;
+
+pub use commons::file::{
+    clash::ClashRules,
+    conflict::ConflictPolicy,
+    executor::{Execution, Executor},
+    filter::PathFilter,
+    symlink::SymlinkPolicy,
+    traits::{FileOperation, Instantiate},
+};
+pub use logic::{Config, Denest};
+pub use naming::{Naming, NamingMode, Separator};
+pub use selection::FolderSelection;
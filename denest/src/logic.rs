@@ -1,16 +1,70 @@
-use std::{fs, io, path::PathBuf};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{naming::Naming, selection::FolderSelection, wrappers::WrapperPlan};
 
 use commons::file::{
-    errors::CheckBeforeError,
-    is_hidden,
-    model::FileOperationTask,
+    clash::ClashRules,
+    conflict::{resolve_conflicts, ConflictPolicy},
+    errors::{CheckBeforeError, HookError},
+    filesystem::{FileSystem, MemoryFs},
+    filter::PathFilter,
+    ignores::IgnoreRules,
+    model::{FailedFileOperation, FileOperationTask},
+    ordering::vacated_paths,
+    symlink::SymlinkPolicy,
     traits::{ExecuteTask, FileOperation, Instantiate, ToFailed, ToFileTask},
-    {filter_by_extension, is_in_working_dir, read_dirs, read_files, walkdir},
+    transfer::move_path,
+    {
+        filter_by_extension, normalize, read_dirs, read_files, read_files_ignoring, walkdir,
+        without_hidden,
+    },
 };
+
+const IGNORE_FILE: &str = ".denestignore";
+
 pub struct Config {
     extensions: Vec<String>,
     depth: Option<u8>,
     cleanup: bool,
+    on_conflict: ConflictPolicy,
+    filter: PathFilter,
+    respect_ignore: bool,
+    symlinks: SymlinkPolicy,
+    clash_rules: ClashRules,
+    naming: Naming,
+    target: Option<PathBuf>,
+    include_root: bool,
+    keep_levels: usize,
+    min_depth: Option<u8>,
+    folders: FolderSelection,
+    collapse_wrappers: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            extensions: vec![],
+            depth: None,
+            cleanup: false,
+            on_conflict: ConflictPolicy::default(),
+            filter: PathFilter::default(),
+            respect_ignore: true,
+            symlinks: SymlinkPolicy::default(),
+            clash_rules: ClashRules::default(),
+            naming: Naming::default(),
+            target: None,
+            include_root: false,
+            keep_levels: 0,
+            min_depth: None,
+            folders: FolderSelection::default(),
+            collapse_wrappers: false,
+        }
+    }
 }
 
 impl Config {
@@ -19,112 +73,389 @@ impl Config {
             extensions,
             depth,
             cleanup,
+            ..Self::default()
         }
     }
+
+    pub fn on_conflict(mut self, on_conflict: ConflictPolicy) -> Self {
+        self.on_conflict = on_conflict;
+        self
+    }
+
+    pub fn filter(mut self, filter: PathFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Skips the paths listed in .gitignore, .ignore and the tool's ignore file.
+    pub fn respect_ignore(mut self, respect_ignore: bool) -> Self {
+        self.respect_ignore = respect_ignore;
+        self
+    }
+
+    pub fn symlinks(mut self, symlinks: SymlinkPolicy) -> Self {
+        self.symlinks = symlinks;
+        self
+    }
+
+    pub fn clash_rules(mut self, clash_rules: ClashRules) -> Self {
+        self.clash_rules = clash_rules;
+        self
+    }
+
+    pub fn naming(mut self, naming: Naming) -> Self {
+        self.naming = naming;
+        self
+    }
+
+    /// Moves the files into `target` instead of the working directory, a
+    /// relative `target` is resolved against the working directory.
+    pub fn target(mut self, target: Option<PathBuf>) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Moves the files directly in the working directory into the target as
+    /// well, they are left where they are otherwise.
+    pub fn include_root(mut self, include_root: bool) -> Self {
+        self.include_root = include_root;
+        self
+    }
+
+    /// Keeps the first `keep_levels` folders below the working directory, the
+    /// files are only hoisted out of the folders below them.
+    pub fn keep_levels(mut self, keep_levels: usize) -> Self {
+        self.keep_levels = keep_levels;
+        self
+    }
+
+    /// Leaves the files less than `min_depth` deep where they are, the files
+    /// directly in the working directory are 1 deep.
+    pub fn min_depth(mut self, min_depth: Option<u8>) -> Self {
+        self.min_depth = min_depth;
+        self
+    }
+
+    pub fn folders(mut self, folders: FolderSelection) -> Self {
+        self.folders = folders;
+        self
+    }
+
+    /// Moves folders instead of files, collapsing every folder whose only
+    /// entry is another folder. The file options are not used then.
+    pub fn collapse_wrappers(mut self, collapse_wrappers: bool) -> Self {
+        self.collapse_wrappers = collapse_wrappers;
+        self
+    }
+
+    fn selects(&self, relative_path: &Path, file_count: usize) -> bool {
+        let deep_enough = self
+            .min_depth
+            .is_none_or(|min| relative_path.components().count() >= usize::from(min));
+        let relative_dir = relative_path.parent().unwrap_or(Path::new(""));
+        deep_enough && self.folders.is_selected(relative_dir, file_count)
+    }
 }
 
 pub struct Denest {
+    fs: Arc<dyn FileSystem>,
     working_dir: PathBuf,
     tasks: Vec<FileOperationTask>,
+    skipped_tasks: Vec<FailedFileOperation>,
+    scan_errors: Vec<FailedFileOperation>,
     failed_tasks: Vec<(usize, io::Error)>,
+    clash_rules: ClashRules,
+    target: PathBuf,
+    keep_levels: usize,
+    collapsed: Vec<PathBuf>,
+    // clashes of the wrapper moves the check cannot find on disk
+    wrapper_clashes: Option<CheckBeforeError>,
     cleanup: bool,
 }
 
 impl Instantiate<Config> for Denest {
-    fn new(working_dir: PathBuf, config: Config) -> Self {
-        let files = if let Some(depth) = config.depth {
-            read_files(&working_dir, Some(depth.into()))
+    fn with_fs(fs: Arc<dyn FileSystem>, working_dir: PathBuf, config: Config) -> Self {
+        if config.collapse_wrappers {
+            return Self::collapsing_wrappers(fs, working_dir, config);
+        }
+        let depth = config.depth.map(usize::from);
+        let target = match &config.target {
+            Some(target) => normalize(&working_dir.join(target)),
+            None => working_dir.clone(),
+        };
+        let (files, scan_errors) = if config.respect_ignore {
+            read_files_ignoring(
+                fs.as_ref(),
+                &working_dir,
+                depth,
+                IGNORE_FILE,
+                config.symlinks,
+            )
         } else {
-            read_files(&working_dir, None)
+            read_files(fs.as_ref(), &working_dir, depth, config.symlinks)
         };
-        let filtered_files = filter_by_extension(files, &config.extensions)
+        let mut file_counts = HashMap::<&Path, usize>::new();
+        for file in files
+            .iter()
+            .filter(|file| !fs.is_hidden(file).unwrap_or(false))
+        {
+            if let Some(dir) = file.parent() {
+                *file_counts.entry(dir).or_default() += 1;
+            }
+        }
+        let selected = files
+            .iter()
+            .filter(|file| {
+                let relative_path = file.strip_prefix(&working_dir).unwrap_or(file);
+                let file_count = file
+                    .parent()
+                    .and_then(|dir| file_counts.get(dir))
+                    .copied()
+                    .unwrap_or_default();
+                config.selects(relative_path, file_count)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        let files = filter_by_extension(selected, &config.extensions);
+        let nested_files = config
+            .filter
+            .apply(&working_dir, files)
             .into_iter()
-            .filter(|file| is_in_working_dir(&working_dir, file) && !is_hidden(file))
+            .filter(|file| {
+                let (dir, _) = destination(&working_dir, &target, config.keep_levels, file);
+                let in_root = file.parent() == Some(working_dir.as_path());
+                file.parent() != Some(dir.as_path()) && (config.include_root || !in_root)
+            })
             .collect::<Vec<_>>();
+        let (filtered_files, unreadable) = without_hidden(fs.as_ref(), nested_files);
         let mut denest = Self {
+            fs,
             working_dir,
             tasks: vec![],
+            skipped_tasks: vec![],
+            scan_errors: scan_errors.iter().map(FailedFileOperation::from).collect(),
             failed_tasks: vec![],
+            clash_rules: config.clash_rules,
+            target,
+            keep_levels: config.keep_levels,
+            collapsed: vec![],
+            wrapper_clashes: None,
             cleanup: config.cleanup,
         };
-        denest.create_tasks(filtered_files);
+        denest.create_tasks(filtered_files, &config.naming, config.on_conflict);
+        denest.skipped_tasks.extend(unreadable);
         denest
     }
 }
 
 impl Denest {
-    fn create_tasks(&mut self, files: Vec<PathBuf>) {
-        self.tasks = files.to_file_tasks(|from| {
-            let mut to = self.working_dir.clone();
-            let filename = &from.file_name().unwrap().to_string_lossy().to_string();
-            to.push(filename);
-            FileOperationTask::new(from, to)
+    fn collapsing_wrappers(fs: Arc<dyn FileSystem>, working_dir: PathBuf, config: Config) -> Self {
+        // ignored paths, skipped links and unreadable folders are kept in the snapshot, so the
+        // folders holding them are not taken for wrappers
+        let mut rules = IgnoreRules::new(fs.as_ref(), &working_dir, IGNORE_FILE);
+        let mut ignored = HashSet::new();
+        let (paths, scan_errors) =
+            fs.scan_filtered(&working_dir, None, false, &mut |path, is_dir| {
+                let skipped_link = config.symlinks == SymlinkPolicy::Skip && fs.is_symlink(path);
+                if skipped_link || (config.respect_ignore && rules.is_ignored(path, is_dir)) {
+                    ignored.insert(path.to_path_buf());
+                    return false;
+                }
+                if is_dir && config.respect_ignore {
+                    rules.add_dir(fs.as_ref(), path, IGNORE_FILE);
+                }
+                true
+            });
+        let scan_errors = scan_errors
+            .iter()
+            .map(FailedFileOperation::from)
+            .collect::<Vec<_>>();
+        ignored.extend(scan_errors.iter().map(|error| error.file_path.clone()));
+        let snapshot = MemoryFs::snapshot_paths(
+            fs.clone(),
+            &working_dir,
+            paths.into_iter().chain(ignored.iter().cloned()),
+        );
+        let plan = WrapperPlan::new(
+            &snapshot,
+            &working_dir,
+            ignored,
+            config.on_conflict,
+            config.clash_rules,
+        );
+        Self {
+            fs,
+            working_dir: working_dir.clone(),
+            tasks: plan.tasks,
+            skipped_tasks: plan.skipped,
+            scan_errors,
+            failed_tasks: vec![],
+            clash_rules: config.clash_rules,
+            target: working_dir,
+            keep_levels: 0,
+            collapsed: plan.collapsed,
+            wrapper_clashes: plan.clashes,
+            cleanup: config.cleanup,
+        }
+    }
+
+    fn create_tasks(&mut self, files: Vec<PathBuf>, naming: &Naming, on_conflict: ConflictPolicy) {
+        let tasks = files.to_file_tasks(|from| {
+            let (dir, relative_path) =
+                destination(&self.working_dir, &self.target, self.keep_levels, &from);
+            let to = dir.join(naming.file_name(relative_path));
+            FileOperationTask::new(from, to).linked(self.fs.as_ref())
         });
+        (self.tasks, self.skipped_tasks) =
+            resolve_conflicts(self.fs.as_ref(), tasks, on_conflict, self.clash_rules);
+    }
+
+    fn destination_dirs(&self) -> BTreeSet<PathBuf> {
+        self.tasks
+            .iter()
+            .filter_map(|task| task.to.parent())
+            .map(Path::to_path_buf)
+            .collect()
     }
 }
 
+/// The folder `file` is moved into, keeping the first `keep_levels` folders of
+/// its path below `working_dir`, and the rest of the path the name is made from.
+fn destination<'a>(
+    working_dir: &Path,
+    target: &Path,
+    keep_levels: usize,
+    file: &'a Path,
+) -> (PathBuf, &'a Path) {
+    let relative_path = file.strip_prefix(working_dir).unwrap_or(file);
+    let parents = relative_path.components().count().saturating_sub(1);
+    let kept = relative_path
+        .components()
+        .take(keep_levels.min(parents))
+        .collect::<PathBuf>();
+    let rest = relative_path.strip_prefix(&kept).unwrap_or(relative_path);
+    (target.join(kept), rest)
+}
+
 impl ExecuteTask for Denest {
     fn check_before_execution(&self) -> Option<CheckBeforeError> {
-        let root_files = walkdir(&self.working_dir, Some(1));
-        let mut would_overwrite = vec![];
-        for task in self.tasks.iter() {
-            for other_task in self.tasks.iter() {
-                if task != other_task || self.tasks.len() == 1 {
-                    let is_nested_clash = task.from != other_task.from && task.to == other_task.to;
-                    let is_outer_clash = root_files.contains(&task.to);
-                    let mut clashing_task_reason = vec![];
-                    if is_nested_clash {
-                        clashing_task_reason.push("would overwrite another moved file");
-                    }
-                    if is_outer_clash {
-                        clashing_task_reason.push("would overwrite a file in root");
-                    }
-                    if !clashing_task_reason.is_empty() {
-                        let fail = task.to_failed(
-                            format!("\n- {}", &clashing_task_reason.join("\n- ")).as_str(),
-                        );
-                        would_overwrite.push(fail);
-                    }
+        let rules = self.clash_rules;
+        let destination_files = rules.index(
+            &self
+                .destination_dirs()
+                .iter()
+                .flat_map(|dir| walkdir(self.fs.as_ref(), dir, Some(1)))
+                .collect::<Vec<_>>(),
+        );
+        let destination = if self.keep_levels > 0 || !self.collapsed.is_empty() {
+            "the destination folder"
+        } else if self.target != self.working_dir {
+            "the target"
+        } else {
+            "root"
+        };
+        let vacated = rules.index(&vacated_paths(&self.tasks));
+        let (mut clashes, mut targets, mut symlinks) = (vec![], vec![], vec![]);
+        for task in self.tasks.iter().filter(|task| !task.overwrites()) {
+            let clashes_with_moved = self.tasks.iter().any(|other_task| {
+                task.from != other_task.from && rules.clash(&task.to, &other_task.to)
+            });
+            if clashes_with_moved {
+                clashes.push(task.to_failed("would overwrite another moved file"));
+            }
+            if destination_files.contains(&task.to) && !vacated.contains(&task.to) {
+                let (failed, replaced) = if self.fs.is_symlink(&task.to) {
+                    (&mut symlinks, "a symlink")
+                } else {
+                    (&mut targets, "a file")
+                };
+                failed
+                    .push(task.to_failed(&format!("would overwrite {replaced} in {destination}")));
+                let destination_file = task.to.to_failed(&format!(
+                    "would be overwritten in {destination} by the move of a nested file"
+                ));
+                if !failed.contains(&destination_file) {
+                    failed.push(destination_file);
                 }
             }
-            let root_file = task
-                .to
-                .to_failed("would be overwritten in root by the move of a nested file");
-            if root_files.contains(&task.to) && !would_overwrite.contains(&root_file) {
-                would_overwrite.push(root_file);
+        }
+        if let Some(e) = &self.wrapper_clashes {
+            let files = e.files().iter().cloned();
+            match e {
+                CheckBeforeError::Clash(_) => clashes.extend(files),
+                CheckBeforeError::Target(_) => targets.extend(files),
+                CheckBeforeError::Symlink(_) => symlinks.extend(files),
             }
         }
-        if !would_overwrite.is_empty() {
-            would_overwrite.sort();
-            Some(CheckBeforeError::FilesWouldOverwrite(would_overwrite))
-        } else {
-            None
+        CheckBeforeError::first(clashes, targets, symlinks)
+    }
+
+    fn before_execute(&self) -> Result<bool, HookError> {
+        let mut created = false;
+        for dir in self.destination_dirs() {
+            if !self.fs.is_dir(&dir) {
+                self.fs.create_dir_all(&dir)?;
+                created = true;
+            }
         }
+        Ok(created)
     }
 
-    fn execute_task(task: &FileOperationTask) -> io::Result<()> {
-        fs::rename(&task.from, &task.to)
+    fn execute_task(&self, task: &FileOperationTask) -> io::Result<u64> {
+        move_path(self.fs.as_ref(), &task.from, &task.to)
     }
 
-    fn after_execute(&self) -> Result<bool, ()> {
+    fn after_execute(&self) -> Result<bool, HookError> {
+        let mut dirs = self.collapsed.clone();
         if self.cleanup {
-            let mut dirs = read_dirs(&self.working_dir, None);
-            dirs.reverse();
-            dirs.into_iter().for_each(|dir| {
-                let _ = fs::remove_dir(dir);
-            });
-            Ok(true)
-        } else {
-            Ok(false)
+            dirs.extend(
+                read_dirs(self.fs.as_ref(), &self.working_dir, None)
+                    .into_iter()
+                    .rev(),
+            );
+        }
+        if dirs.is_empty() {
+            return Ok(false);
+        }
+        let mut first_error = None;
+        for dir in dirs.iter().filter(|dir| self.fs.is_dir(dir)) {
+            match self.fs.remove_dir(dir) {
+                // folders that still hold files are meant to stay
+                Err(e) if e.kind() == io::ErrorKind::DirectoryNotEmpty => {}
+                Err(e) if first_error.is_none() => {
+                    first_error = Some(HookError::Failed(format!(
+                        "{} could not be removed: {e}",
+                        dir.display()
+                    )));
+                }
+                _ => {}
+            }
         }
+        first_error.map_or(Ok(true), Err)
     }
 }
 
 impl FileOperation<Config> for Denest {
+    fn get_fs(&self) -> &dyn FileSystem {
+        self.fs.as_ref()
+    }
+
     fn get_tasks(&self) -> Vec<FileOperationTask> {
         self.tasks.clone()
     }
 
+    fn get_tasks_mut(&mut self) -> &mut Vec<FileOperationTask> {
+        &mut self.tasks
+    }
+
+    fn get_skipped_tasks(&self) -> Vec<FailedFileOperation> {
+        self.skipped_tasks.clone()
+    }
+
+    fn get_scan_errors(&self) -> Vec<FailedFileOperation> {
+        self.scan_errors.clone()
+    }
+
     fn get_failed_tasks(&self) -> &Vec<(usize, io::Error)> {
         &self.failed_tasks
     }
@@ -133,3 +464,529 @@ impl FileOperation<Config> for Denest {
         &mut self.failed_tasks
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::naming::{NamingMode, Separator};
+    use commons::file::{errors::ExecutionError, executor::Executor, filesystem::MemoryFs};
+
+    fn denest(fs: &Arc<MemoryFs>, cleanup: bool, on_conflict: ConflictPolicy) -> Denest {
+        with_config(
+            fs,
+            Config::new(vec![], None, cleanup).on_conflict(on_conflict),
+        )
+    }
+
+    fn with_config(fs: &Arc<MemoryFs>, config: Config) -> Denest {
+        Denest::with_fs(fs.clone(), PathBuf::from("/work"), config)
+    }
+
+    fn task(from: &str, to: &str) -> FileOperationTask {
+        FileOperationTask::new(PathBuf::from(from), PathBuf::from(to))
+    }
+
+    #[test]
+    fn plans_nested_files_into_the_working_dir() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/root.txt", "");
+        fs.add_file("/work/a/one.txt", "");
+        fs.add_file("/work/a/b/two.txt", "");
+        fs.add_file("/work/a/.hidden", "");
+        let denest = denest(&fs, false, ConflictPolicy::Abort);
+        assert_eq!(
+            denest.get_tasks(),
+            vec![
+                task("/work/a/b/two.txt", "/work/two.txt"),
+                task("/work/a/one.txt", "/work/one.txt"),
+            ]
+        );
+        assert!(denest.check_before_execution().is_none());
+    }
+
+    #[test]
+    fn respects_ignore_files() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/.gitignore", "target/\n*.log\n");
+        fs.add_file("/work/.denestignore", "!keep.log\n");
+        fs.add_file("/work/target/debug/app", "");
+        fs.add_file("/work/a/run.log", "");
+        fs.add_file("/work/a/keep.log", "");
+        fs.add_file("/work/a/b/.ignore", "skip.txt\n");
+        fs.add_file("/work/a/b/skip.txt", "");
+        fs.add_file("/work/a/b/take.txt", "");
+        let denest = denest(&fs, false, ConflictPolicy::Abort);
+        assert_eq!(
+            denest.get_tasks(),
+            vec![
+                task("/work/a/b/take.txt", "/work/take.txt"),
+                task("/work/a/keep.log", "/work/keep.log"),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_folders_that_could_not_be_scanned() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/a/one.txt", "");
+        fs.add_file("/work/private/two.txt", "");
+        fs.deny_read("/work/private");
+        let denest = denest(&fs, false, ConflictPolicy::Abort);
+        assert_eq!(
+            denest.get_tasks(),
+            vec![task("/work/a/one.txt", "/work/one.txt")]
+        );
+        assert_eq!(
+            denest.get_scan_errors(),
+            vec![PathBuf::from("/work/private").to_failed("permission denied")]
+        );
+    }
+
+    #[test]
+    fn does_not_scan_ignored_folders() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/.gitignore", "target/\n");
+        fs.add_file("/work/a/one.txt", "");
+        fs.add_file("/work/target/debug/app", "");
+        fs.deny_read("/work/target/debug");
+        let denest = denest(&fs, false, ConflictPolicy::Abort);
+        assert_eq!(
+            denest.get_tasks(),
+            vec![task("/work/a/one.txt", "/work/one.txt")]
+        );
+        assert!(denest.get_scan_errors().is_empty());
+    }
+
+    #[test]
+    fn moves_links_themselves_and_records_their_targets() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/a/one.txt", "one");
+        fs.add_link("/work/a/alias.txt", "one.txt");
+        let mut denest = denest(&fs, false, ConflictPolicy::Abort);
+        let tasks = denest.get_tasks();
+        assert_eq!(tasks[0].link, Some(PathBuf::from("one.txt")));
+        assert_eq!(
+            tasks[0].to_string(),
+            "/work/a/alias.txt -> /work/alias.txt (symlink to one.txt)"
+        );
+        assert_eq!(tasks[1].link, None);
+        assert_eq!(denest.execute().failed, 0);
+        assert!(fs.is_symlink(Path::new("/work/alias.txt")));
+        assert_eq!(fs.files(), vec![PathBuf::from("/work/one.txt")]);
+    }
+
+    #[test]
+    fn naming_by_folders_avoids_clashes() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/Season1/ep01.mkv", "");
+        fs.add_file("/work/Season2/ep01.mkv", "");
+        let config =
+            Config::default().naming(Naming::new(NamingMode::Prefix, Separator::default(), None));
+        let denest = with_config(&fs, config);
+        assert_eq!(
+            denest.get_tasks(),
+            vec![
+                task("/work/Season1/ep01.mkv", "/work/Season1 - ep01.mkv"),
+                task("/work/Season2/ep01.mkv", "/work/Season2 - ep01.mkv"),
+            ]
+        );
+        assert!(denest.check_before_execution().is_none());
+    }
+
+    #[test]
+    fn collects_files_into_a_target_outside_the_tree() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/root.txt", "");
+        fs.add_file("/work/a/one.txt", "");
+        fs.add_file("/work/a/b/two.txt", "");
+        fs.add_file("/out/two.txt", "");
+        let config = Config::default().target(Some(PathBuf::from("../out")));
+        let denest = with_config(&fs, config);
+        assert_eq!(
+            denest.get_tasks(),
+            vec![
+                task("/work/a/b/two.txt", "/out/two.txt"),
+                task("/work/a/one.txt", "/out/one.txt"),
+            ]
+        );
+
+        let config = Config::default()
+            .target(Some(PathBuf::from("../out")))
+            .include_root(true);
+        let denest = with_config(&fs, config);
+        assert_eq!(
+            denest.get_tasks(),
+            vec![
+                task("/work/a/b/two.txt", "/out/two.txt"),
+                task("/work/a/one.txt", "/out/one.txt"),
+                task("/work/root.txt", "/out/root.txt"),
+            ]
+        );
+        let Some(CheckBeforeError::Target(files)) = denest.check_before_execution() else {
+            panic!("the clash with the target should have been detected");
+        };
+        let paths = files
+            .iter()
+            .map(|file| file.file_path.clone())
+            .collect::<Vec<_>>();
+        assert!(paths.contains(&PathBuf::from("/out/two.txt")));
+        assert!(paths.contains(&PathBuf::from("/work/a/b/two.txt")));
+        assert!(!paths.contains(&PathBuf::from("/work/a/one.txt")));
+    }
+
+    fn keep_levels(fs: &Arc<MemoryFs>, keep_levels: usize) -> Denest {
+        let config = Config::new(vec![], None, true).keep_levels(keep_levels);
+        with_config(fs, config)
+    }
+
+    #[test]
+    fn keeps_the_first_levels_and_collapses_the_rest() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/top.mp3", "");
+        fs.add_file("/work/Artist/single.mp3", "");
+        fs.add_file("/work/Artist/Album/cover.jpg", "");
+        fs.add_file("/work/Artist/Album/CD1/01.mp3", "");
+        fs.add_file("/work/Artist/Album/CD2/deep/02.mp3", "");
+        fs.add_file("/work/Other/Album/CD1/01.mp3", "");
+        let mut denest = keep_levels(&fs, 2);
+        assert_eq!(
+            denest.get_tasks(),
+            vec![
+                task("/work/Artist/Album/CD1/01.mp3", "/work/Artist/Album/01.mp3"),
+                task(
+                    "/work/Artist/Album/CD2/deep/02.mp3",
+                    "/work/Artist/Album/02.mp3"
+                ),
+                task("/work/Other/Album/CD1/01.mp3", "/work/Other/Album/01.mp3"),
+            ]
+        );
+        assert!(denest.check_before_execution().is_none());
+        assert_eq!(denest.execute().failed, 0);
+        assert!(matches!(denest.after_execute(), Ok(true)));
+        assert!(fs.is_dir(Path::new("/work/Artist/Album")));
+        assert!(!fs.exists(Path::new("/work/Artist/Album/CD1")));
+        assert!(!fs.exists(Path::new("/work/Artist/Album/CD2")));
+        assert!(!fs.exists(Path::new("/work/Other/Album/CD1")));
+    }
+
+    #[test]
+    fn scopes_clashes_to_the_destination_folder() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/A/notes.txt", "");
+        fs.add_file("/work/A/x/notes.txt", "");
+        fs.add_file("/work/A/y/01.mp3", "");
+        fs.add_file("/work/A/z/01.mp3", "");
+        fs.add_file("/work/B/x/notes.txt", "");
+        let denest = keep_levels(&fs, 1);
+        let Some(CheckBeforeError::Clash(files)) = denest.check_before_execution() else {
+            panic!("clashes should have been detected");
+        };
+        let paths = files
+            .iter()
+            .map(|file| file.file_path.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("/work/A/y/01.mp3"),
+                PathBuf::from("/work/A/z/01.mp3")
+            ]
+        );
+
+        fs.remove_file(Path::new("/work/A/z/01.mp3")).unwrap();
+        let denest = keep_levels(&fs, 1);
+        let Some(CheckBeforeError::Target(files)) = denest.check_before_execution() else {
+            panic!("the clash with the existing file should have been detected");
+        };
+        let paths = files
+            .iter()
+            .map(|file| file.file_path.clone())
+            .collect::<Vec<_>>();
+        assert!(paths.contains(&PathBuf::from("/work/A/notes.txt")));
+        assert!(paths.contains(&PathBuf::from("/work/A/x/notes.txt")));
+        assert!(!paths.contains(&PathBuf::from("/work/B/x/notes.txt")));
+    }
+
+    #[test]
+    fn hoists_only_from_selected_folders_deep_enough() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/movie/movie.mkv", "");
+        fs.add_file("/work/movie/.nfo", "");
+        fs.add_file("/work/show/ep01.mkv", "");
+        fs.add_file("/work/show/ep02.mkv", "");
+        fs.add_file("/work/show/extras/CD1/making-of.mkv", "");
+        fs.add_file("/work/show/extras/CD1/trailer.mkv", "");
+        let config =
+            Config::default().folders(FolderSelection::new(vec!["CD*".parse().unwrap()], Some(1)));
+        let denest = with_config(&fs, config);
+        assert_eq!(
+            denest.get_tasks(),
+            vec![
+                task("/work/movie/movie.mkv", "/work/movie.mkv"),
+                task("/work/show/extras/CD1/making-of.mkv", "/work/making-of.mkv"),
+                task("/work/show/extras/CD1/trailer.mkv", "/work/trailer.mkv"),
+            ]
+        );
+
+        let config = Config::default().min_depth(Some(3));
+        let denest = with_config(&fs, config);
+        assert_eq!(
+            denest.get_tasks(),
+            vec![
+                task("/work/show/extras/CD1/making-of.mkv", "/work/making-of.mkv"),
+                task("/work/show/extras/CD1/trailer.mkv", "/work/trailer.mkv"),
+            ]
+        );
+    }
+
+    #[test]
+    fn collapses_wrappers_and_checks_their_contents_for_clashes() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/a/a/a/file.txt", "");
+        fs.add_file("/work/x/x/x/inner.txt", "");
+        fs.add_file("/work/x/x/y.txt", "");
+        let config = Config::default().collapse_wrappers(true);
+        let mut denest = with_config(&fs, config);
+        assert_eq!(
+            denest.get_tasks(),
+            vec![
+                task("/work/a/a/a/file.txt", "/work/a/file.txt"),
+                task("/work/x/x/x", "/work/x/x"),
+                task("/work/x/x/y.txt", "/work/x/y.txt"),
+            ]
+        );
+        let Some(CheckBeforeError::Target(files)) = denest.check_before_execution() else {
+            panic!("the wrapped folder should clash with its wrapper");
+        };
+        let paths = files
+            .iter()
+            .map(|file| file.file_path.clone())
+            .collect::<Vec<_>>();
+        assert!(paths.contains(&PathBuf::from("/work/x/x/x")));
+        assert!(!paths.contains(&PathBuf::from("/work/a/a/a/file.txt")));
+
+        denest
+            .get_tasks_mut()
+            .retain(|task| task.from.starts_with("/work/a"));
+        assert_eq!(denest.execute().failed, 0);
+        assert!(matches!(denest.after_execute(), Ok(true)));
+        assert!(fs.exists(Path::new("/work/a/file.txt")));
+        assert!(!fs.exists(Path::new("/work/a/a")));
+    }
+
+    #[test]
+    fn collapses_wrappers_around_ignored_folders_and_reports_scan_errors() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/.gitignore", "build/\n");
+        fs.add_file("/work/a/a/file.txt", "");
+        fs.add_file("/work/b/build/out/app", "");
+        fs.add_file("/work/c/c/locked/secret", "");
+        fs.deny_read("/work/c/c/locked");
+        let denest = with_config(&fs, Config::default().collapse_wrappers(true));
+        assert_eq!(
+            denest.get_tasks(),
+            vec![task("/work/a/a/file.txt", "/work/a/file.txt")]
+        );
+        let scan_errors = denest.get_scan_errors();
+        assert_eq!(scan_errors.len(), 1);
+        assert_eq!(scan_errors[0].file_path, PathBuf::from("/work/c/c/locked"));
+    }
+
+    #[test]
+    fn checks_wrapper_moves_of_later_rounds_for_clashes() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/dl/proj/README", "");
+        fs.add_file("/work/dl/proj/src/lib.rs", "");
+        fs.add_file("/work/dl/proj/src/only/deeper/deeper", "");
+        fs.add_file("/work/dl/proj/src/only/deeper/other", "");
+        let denest = with_config(&fs, Config::default().collapse_wrappers(true));
+        let Some(CheckBeforeError::Target(files)) = denest.check_before_execution() else {
+            panic!("the inner file should clash with its wrapper");
+        };
+        let paths = files
+            .iter()
+            .map(|file| file.file_path.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, vec![PathBuf::from("/work/src/only/deeper/deeper")]);
+    }
+
+    #[test]
+    fn detects_clashes_between_nested_and_root_files() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/same.txt", "");
+        fs.add_file("/work/a/same.txt", "");
+        fs.add_file("/work/b/other.txt", "");
+        fs.add_file("/work/c/other.txt", "");
+        let clashing = denest(&fs, false, ConflictPolicy::Abort);
+        let Some(CheckBeforeError::Clash(files)) = clashing.check_before_execution() else {
+            panic!("clashes should have been detected");
+        };
+        let paths = files
+            .iter()
+            .map(|file| file.file_path.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("/work/b/other.txt"),
+                PathBuf::from("/work/c/other.txt")
+            ]
+        );
+
+        fs.remove_file(Path::new("/work/c/other.txt")).unwrap();
+        let existing = denest(&fs, false, ConflictPolicy::Abort);
+        let Some(CheckBeforeError::Target(files)) = existing.check_before_execution() else {
+            panic!("the clash with the root file should have been detected");
+        };
+        let paths = files
+            .iter()
+            .map(|file| file.file_path.clone())
+            .collect::<Vec<_>>();
+        assert!(paths.contains(&PathBuf::from("/work/same.txt")));
+        assert!(paths.contains(&PathBuf::from("/work/a/same.txt")));
+    }
+
+    #[test]
+    fn detects_links_that_would_be_replaced() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_link("/work/same.txt", "missing.txt");
+        fs.add_file("/work/a/same.txt", "");
+        let Some(CheckBeforeError::Symlink(files)) =
+            denest(&fs, false, ConflictPolicy::Abort).check_before_execution()
+        else {
+            panic!("the dangling link should have been detected");
+        };
+        assert!(files
+            .iter()
+            .any(|file| file.file_path == Path::new("/work/a/same.txt")));
+    }
+
+    #[test]
+    fn suffix_policy_resolves_clashes() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/same.txt", "");
+        fs.add_file("/work/a/same.txt", "");
+        let mut denest = denest(&fs, false, ConflictPolicy::Suffix);
+        assert!(denest.check_before_execution().is_none());
+        let result = denest.execute();
+        assert_eq!(result.failed, 0);
+        assert!(fs.exists(Path::new("/work/same (1).txt")));
+        assert!(fs.exists(Path::new("/work/same.txt")));
+    }
+
+    #[test]
+    fn executes_moves_and_cleans_up_empty_folders() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/a/one.txt", "");
+        fs.add_file("/work/a/b/two.txt", "");
+        fs.add_dir("/work/empty");
+        let mut denest = denest(&fs, true, ConflictPolicy::Abort);
+        let result = denest.execute();
+        assert_eq!((result.successful, result.failed), (2, 0));
+        assert_eq!(result.journal.len(), 2);
+        assert!(matches!(denest.after_execute(), Ok(true)));
+        assert_eq!(
+            fs.paths(),
+            vec![
+                PathBuf::from("/"),
+                PathBuf::from("/work"),
+                PathBuf::from("/work/one.txt"),
+                PathBuf::from("/work/two.txt"),
+            ]
+        );
+    }
+
+    #[test]
+    fn cleanup_keeps_folders_with_remaining_files() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/a/one.txt", "");
+        fs.add_file("/work/a/stuck.txt", "");
+        fs.fail_on("/work/a/stuck.txt");
+        let mut denest = denest(&fs, true, ConflictPolicy::Abort);
+        denest.execute();
+        assert!(matches!(denest.after_execute(), Ok(true)));
+        assert!(fs.exists(Path::new("/work/a/stuck.txt")));
+    }
+
+    #[test]
+    fn cleanup_reports_folders_that_cannot_be_removed() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/a/one.txt", "");
+        fs.add_dir("/work/locked");
+        fs.fail_on("/work/locked");
+        let mut denest = denest(&fs, true, ConflictPolicy::Abort);
+        denest.execute();
+        assert!(matches!(
+            denest.after_execute(),
+            Err(HookError::Failed(message)) if message.starts_with("/work/locked")
+        ));
+        assert!(!fs.exists(Path::new("/work/a")));
+    }
+
+    #[test]
+    fn reports_failed_moves_with_their_task() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/a/one.txt", "");
+        fs.add_file("/work/b/two.txt", "");
+        fs.fail_on("/work/b/two.txt");
+        let mut denest = denest(&fs, false, ConflictPolicy::Abort);
+        let result = denest.execute();
+        assert_eq!((result.successful, result.failed), (1, 1));
+        let failed = denest.get_failed_operations();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].file_path, PathBuf::from("/work/b/two.txt"));
+        assert!(fs.exists(Path::new("/work/one.txt")));
+    }
+
+    #[test]
+    fn atomic_execution_rolls_back_on_failure() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/a/one.txt", "");
+        fs.add_file("/work/b/two.txt", "");
+        fs.fail_on("/work/b/two.txt");
+        let mut denest = denest(&fs, false, ConflictPolicy::Abort);
+        let result = denest.execute_transactional();
+        assert_eq!((result.successful, result.failed), (0, 1));
+        assert_eq!(
+            result.rolled_back,
+            vec![task("/work/a/one.txt", "/work/one.txt")]
+        );
+        assert!(result.unrecoverable.is_empty());
+        assert!(fs.exists(Path::new("/work/a/one.txt")));
+        assert!(!fs.exists(Path::new("/work/one.txt")));
+    }
+
+    #[test]
+    fn revalidates_curated_tasks() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/root.txt", "");
+        fs.add_file("/work/a/one.txt", "");
+        let mut denest = denest(&fs, false, ConflictPolicy::Abort);
+        assert!(denest.check_before_execution().is_none());
+        denest.get_tasks_mut()[0].to = PathBuf::from("/work/root.txt");
+        assert!(denest.check_before_execution().is_some());
+    }
+
+    #[test]
+    fn executor_runs_checks_hooks_and_journal() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/a/one.txt", "");
+        let mut hoisting = denest(&fs, true, ConflictPolicy::Abort);
+        let execution = Executor::new()
+            .journal("denest", Path::new("/work"))
+            .run(&mut hoisting)
+            .unwrap();
+        assert_eq!(execution.result.successful, 1);
+        assert!(execution.failures.is_empty());
+        let journal = execution.journal.unwrap().unwrap();
+        assert!(fs.exists(&journal));
+        assert!(!fs.exists(Path::new("/work/a")));
+
+        fs.add_file("/work/b/one.txt", "");
+        let mut clashing = denest(&fs, true, ConflictPolicy::Abort);
+        assert!(matches!(
+            Executor::new().run(&mut clashing),
+            Err(ExecutionError::Check(_))
+        ));
+    }
+}
@@ -0,0 +1,192 @@
+use std::{
+    ffi::{OsStr, OsString},
+    fmt::Display,
+    path::{Component, Path},
+    str::FromStr,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NamingMode {
+    /// Keep the file name as it is
+    #[default]
+    Keep,
+    /// Put the parent folders in front of the file name
+    Prefix,
+    /// Put the parent folders after the file stem, before the extension
+    Suffix,
+}
+
+impl NamingMode {
+    pub const VARIANTS: &'static [&'static str] = &["keep", "prefix", "suffix"];
+}
+
+impl FromStr for NamingMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(Self::Keep),
+            "prefix" => Ok(Self::Prefix),
+            "suffix" => Ok(Self::Suffix),
+            _ => Err(format!("unknown naming mode '{s}'")),
+        }
+    }
+}
+
+impl Display for NamingMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Keep => "keep",
+            Self::Prefix => "prefix",
+            Self::Suffix => "suffix",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// The text between the folder names and the file name, it cannot hold
+/// anything that would split the new name into folders.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Separator(String);
+
+impl Default for Separator {
+    fn default() -> Self {
+        Self(" - ".to_string())
+    }
+}
+
+impl FromStr for Separator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let forbidden: &[char] = if cfg!(windows) {
+            &['/', '\\', '\0']
+        } else {
+            &['/', '\0']
+        };
+        match s.chars().find(|c| forbidden.contains(c)) {
+            Some(c) => Err(format!("separator cannot contain {c:?}")),
+            None => Ok(Self(s.to_string())),
+        }
+    }
+}
+
+/// How the name of a hoisted file is made from the folders it was nested in,
+/// e.g. `Season1/ep01.mkv` becomes `Season1 - ep01.mkv` with a prefix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Naming {
+    mode: NamingMode,
+    separator: Separator,
+    parent_levels: Option<usize>,
+}
+
+impl Default for Naming {
+    fn default() -> Self {
+        Self::new(NamingMode::Keep, Separator::default(), None)
+    }
+}
+
+impl Naming {
+    pub fn new(mode: NamingMode, separator: Separator, parent_levels: Option<usize>) -> Self {
+        Self {
+            mode,
+            separator,
+            parent_levels,
+        }
+    }
+
+    /// The new name of the file at `relative_path`, a path relative to the
+    /// folder the file is hoisted out of. Only the `parent_levels` closest
+    /// parents are kept.
+    pub fn file_name(&self, relative_path: &Path) -> OsString {
+        let name = relative_path.file_name().unwrap_or_default();
+        let mut parents = relative_path
+            .parent()
+            .into_iter()
+            .flat_map(Path::components)
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if let Some(levels) = self.parent_levels {
+            parents.drain(..parents.len().saturating_sub(levels));
+        }
+        if parents.is_empty() {
+            return name.to_os_string();
+        }
+        let parents = self.join(&parents);
+        match self.mode {
+            NamingMode::Keep => name.to_os_string(),
+            NamingMode::Prefix => self.join(&[&parents, name]),
+            NamingMode::Suffix => {
+                let path = Path::new(name);
+                let stem = path.file_stem().unwrap_or(name);
+                let mut suffixed = self.join(&[stem, &parents]);
+                if let Some(extension) = path.extension() {
+                    suffixed.push(".");
+                    suffixed.push(extension);
+                }
+                suffixed
+            }
+        }
+    }
+
+    fn join(&self, parts: &[&OsStr]) -> OsString {
+        let mut joined = OsString::new();
+        for (i, part) in parts.iter().enumerate() {
+            if i > 0 {
+                joined.push(&self.separator.0);
+            }
+            joined.push(part);
+        }
+        joined
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn separator(s: &str) -> Separator {
+        s.parse().unwrap()
+    }
+
+    fn name(naming: &Naming, path: &str) -> String {
+        naming
+            .file_name(Path::new(path))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    #[test]
+    fn prefixes_and_suffixes_the_parent_folders() {
+        let prefix = Naming::new(NamingMode::Prefix, separator(" - "), None);
+        assert_eq!(name(&prefix, "Season1/ep01.mkv"), "Season1 - ep01.mkv");
+        assert_eq!(name(&prefix, "Show/S1/ep01.mkv"), "Show - S1 - ep01.mkv");
+        assert_eq!(name(&prefix, "top.mkv"), "top.mkv");
+
+        let suffix = Naming::new(NamingMode::Suffix, separator("_"), None);
+        assert_eq!(name(&suffix, "Show/S1/ep01.mkv"), "ep01_Show_S1.mkv");
+        assert_eq!(name(&suffix, "Show/README"), "README_Show");
+
+        assert_eq!(name(&Naming::default(), "Show/S1/ep01.mkv"), "ep01.mkv");
+    }
+
+    #[test]
+    fn keeps_only_the_closest_parent_levels() {
+        let naming = Naming::new(NamingMode::Prefix, separator("."), Some(1));
+        assert_eq!(name(&naming, "Show/S1/ep01.mkv"), "S1.ep01.mkv");
+        let naming = Naming::new(NamingMode::Prefix, separator("."), Some(0));
+        assert_eq!(name(&naming, "Show/S1/ep01.mkv"), "ep01.mkv");
+    }
+
+    #[test]
+    fn rejects_separators_that_split_the_name() {
+        assert!("/".parse::<Separator>().is_err());
+        assert!(" / ".parse::<Separator>().is_err());
+        assert!("\0".parse::<Separator>().is_err());
+        assert_eq!("\\".parse::<Separator>().is_err(), cfg!(windows));
+        assert_eq!(separator(" - "), Separator::default());
+    }
+}
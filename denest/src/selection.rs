@@ -0,0 +1,50 @@
+use commons::file::filter::PathPattern;
+use std::path::Path;
+
+/// Which folders files are hoisted out of, e.g. only the single file wrapper
+/// folders archive tools create. Without patterns and a file limit every
+/// folder is selected.
+#[derive(Debug, Clone, Default)]
+pub struct FolderSelection {
+    patterns: Vec<PathPattern>,
+    max_files: Option<usize>,
+}
+
+impl FolderSelection {
+    pub fn new(patterns: Vec<PathPattern>, max_files: Option<usize>) -> Self {
+        Self {
+            patterns,
+            max_files,
+        }
+    }
+
+    /// Whether the folder at `relative_dir` holding `file_count` files is
+    /// selected, a folder is selected if it matches any of the patterns or
+    /// holds at most `max_files` files.
+    pub fn is_selected(&self, relative_dir: &Path, file_count: usize) -> bool {
+        if self.patterns.is_empty() && self.max_files.is_none() {
+            return true;
+        }
+        self.patterns
+            .iter()
+            .any(|pattern| pattern.is_match(relative_dir))
+            || self.max_files.is_some_and(|max| file_count <= max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selects_folders_by_name_or_file_count() {
+        let path = Path::new;
+        assert!(FolderSelection::default().is_selected(path("any"), 10));
+
+        let selection = FolderSelection::new(vec!["CD*".parse().unwrap()], Some(1));
+        assert!(selection.is_selected(path("Album/CD1"), 12));
+        assert!(selection.is_selected(path("movie"), 1));
+        assert!(!selection.is_selected(path("movie"), 2));
+        assert!(!selection.is_selected(path("CD1/extras"), 3));
+    }
+}
@@ -0,0 +1,341 @@
+use commons::file::{
+    clash::ClashRules,
+    conflict::{resolve_conflicts, ConflictPolicy},
+    errors::CheckBeforeError,
+    filesystem::{FileSystem, MemoryFs},
+    model::{FailedFileOperation, FileOperationTask},
+    ordering::vacated_paths,
+    traits::ToFailed,
+    walkdir,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+    path::{Path, PathBuf},
+};
+
+/// The moves that collapse the folders whose only entry is another folder,
+/// e.g. `a/a/a/file.txt` becomes `a/file.txt`.
+#[derive(Debug, Default)]
+pub struct WrapperPlan {
+    pub tasks: Vec<FileOperationTask>,
+    /// The wrappers emptied by the moves, inner ones first
+    pub collapsed: Vec<PathBuf>,
+    pub skipped: Vec<FailedFileOperation>,
+    /// The moves of later rounds that would overwrite a file, the disk only
+    /// holds the destinations of the first round to check against
+    pub clashes: Option<CheckBeforeError>,
+}
+
+impl WrapperPlan {
+    /// Plans on `snapshot`, a copy of the tree below `root` that the planned
+    /// moves are made on, until no wrapper is left. The `ignored` paths are
+    /// neither moved nor looked into.
+    pub fn new(
+        snapshot: &MemoryFs,
+        root: &Path,
+        ignored: HashSet<PathBuf>,
+        on_conflict: ConflictPolicy,
+        rules: ClashRules,
+    ) -> Self {
+        let mut plan = Self::default();
+        let mut round = Round {
+            fs: snapshot,
+            ignored,
+            tasks: vec![],
+            collapsed: vec![],
+        };
+        // a move brings entries closer to the root, so the rounds end once nothing moves
+        loop {
+            round.collapse(root);
+            let (tasks, skipped) =
+                resolve_conflicts(snapshot, mem::take(&mut round.tasks), on_conflict, rules);
+            plan.skipped.extend(skipped);
+            if !plan.tasks.is_empty() {
+                plan.clashes = clashes(snapshot, &tasks, rules);
+                if plan.clashes.is_some() {
+                    plan.tasks.extend(tasks);
+                    break;
+                }
+            }
+            let stable = !round.apply(&tasks);
+            plan.tasks.extend(tasks);
+            for dir in mem::take(&mut round.collapsed) {
+                if !plan.collapsed.contains(&dir) {
+                    plan.collapsed.push(dir);
+                }
+            }
+            if stable {
+                break;
+            }
+        }
+        plan.skipped.sort();
+        plan.skipped.dedup();
+        plan
+    }
+}
+
+// the moves that would overwrite each other or an entry in `fs`, as the check before execution
+// would find them on disk
+fn clashes(
+    fs: &MemoryFs,
+    tasks: &[FileOperationTask],
+    rules: ClashRules,
+) -> Option<CheckBeforeError> {
+    let vacated = rules.index(&vacated_paths(tasks));
+    let mut destinations = HashMap::<PathBuf, usize>::new();
+    for task in tasks {
+        *destinations.entry(rules.key(&task.to)).or_default() += 1;
+    }
+    let (mut clashes, mut targets, mut symlinks) = (vec![], vec![], vec![]);
+    for task in tasks.iter().filter(|task| !task.overwrites()) {
+        let existing = task.to.parent().and_then(|dir| {
+            walkdir(fs, dir, Some(1))
+                .into_iter()
+                .find(|path| rules.clash(path, &task.to) && !vacated.contains(path))
+        });
+        if destinations[&rules.key(&task.to)] > 1 {
+            clashes.push(task.to_failed("would overwrite another moved file"));
+        } else if existing.as_ref().is_some_and(|path| fs.is_symlink(path)) {
+            symlinks.push(task.to_failed("would replace a symlink in the destination folder"));
+        } else if existing.is_some() {
+            targets.push(task.to_failed("would overwrite a file in the destination folder"));
+        }
+    }
+    CheckBeforeError::first(clashes, targets, symlinks)
+}
+
+struct Round<'a> {
+    fs: &'a MemoryFs,
+    ignored: HashSet<PathBuf>,
+    tasks: Vec<FileOperationTask>,
+    collapsed: Vec<PathBuf>,
+}
+
+impl Round<'_> {
+    fn collapse(&mut self, dir: &Path) {
+        let chain = self.wrapper_chain(dir);
+        let Some(innermost) = chain.last() else {
+            for entry in self.entries(dir) {
+                if self.is_open_dir(&entry) {
+                    self.collapse(&entry);
+                }
+            }
+            return;
+        };
+        for entry in self.entries(innermost) {
+            // ignored entries stay in the wrapper, which is then left in place
+            if self.ignored.contains(&entry) {
+                continue;
+            }
+            // a wrapper that is moved anyway is replaced by the folder it wraps
+            let entry_chain = self.wrapper_chain(&entry);
+            let from = entry_chain.last().cloned().unwrap_or(entry.clone());
+            let to = dir.join(entry.file_name().unwrap_or_default());
+            self.collapsed.extend(
+                [entry.clone()]
+                    .into_iter()
+                    .chain(entry_chain)
+                    .filter(|path| path != &from)
+                    .rev(),
+            );
+            self.tasks.push(FileOperationTask::new(from, to));
+        }
+        self.collapsed.extend(chain.into_iter().rev());
+    }
+
+    // makes the moves on the snapshot, false if there was nothing to move or
+    // a move failed, as planning on would only repeat it
+    fn apply(&mut self, tasks: &[FileOperationTask]) -> bool {
+        for task in tasks {
+            if self.fs.rename(&task.from, &task.to).is_err() {
+                return false;
+            }
+            self.ignored = mem::take(&mut self.ignored)
+                .into_iter()
+                .map(|path| match path.strip_prefix(&task.from) {
+                    Ok(rest) => task.to.join(rest),
+                    Err(_) => path,
+                })
+                .collect();
+        }
+        for dir in &self.collapsed {
+            let _ = self.fs.remove_dir(dir);
+        }
+        !tasks.is_empty()
+    }
+
+    fn entries(&self, dir: &Path) -> Vec<PathBuf> {
+        walkdir(self.fs, dir, Some(1))
+            .into_iter()
+            .filter(|path| path != dir)
+            .collect()
+    }
+
+    fn is_open_dir(&self, path: &Path) -> bool {
+        self.fs.is_dir(path) && !self.fs.is_symlink(path) && !self.ignored.contains(path)
+    }
+
+    // the folders `dir` wraps one inside the other, empty if it is not a wrapper
+    fn wrapper_chain(&self, dir: &Path) -> Vec<PathBuf> {
+        let mut chain = vec![];
+        let mut current = dir.to_path_buf();
+        while self.is_open_dir(&current) {
+            match self.entries(&current).as_slice() {
+                [only] if self.is_open_dir(only) => {
+                    chain.push(only.clone());
+                    current = only.clone();
+                }
+                _ => break,
+            }
+        }
+        chain
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(from: &str, to: &str) -> FileOperationTask {
+        FileOperationTask::new(PathBuf::from(from), PathBuf::from(to))
+    }
+
+    fn plan(fs: &MemoryFs, ignored: &[&str]) -> WrapperPlan {
+        let ignored = ignored.iter().map(PathBuf::from).collect();
+        WrapperPlan::new(
+            fs,
+            Path::new("/work"),
+            ignored,
+            ConflictPolicy::Abort,
+            ClashRules::default(),
+        )
+    }
+
+    #[test]
+    fn collapses_wrappers_until_stable() {
+        let fs = MemoryFs::new();
+        fs.add_file("/work/a/a/a/file.txt", "");
+        fs.add_file("/work/dl/proj/README", "");
+        fs.add_file("/work/dl/proj/src/only/deeper/main.rs", "");
+        fs.add_file("/work/dl/proj/src/lib/x/y.rs", "");
+        fs.add_file("/work/dl/proj/src/lib/z.rs", "");
+        fs.add_file("/work/keep/one.txt", "");
+        fs.add_file("/work/keep/two.txt", "");
+        let plan = plan(&fs, &[]);
+        assert_eq!(
+            plan.tasks,
+            vec![
+                task("/work/a/a/a/file.txt", "/work/a/file.txt"),
+                task("/work/dl/proj/README", "/work/dl/README"),
+                task("/work/dl/proj/src", "/work/dl/src"),
+                // planned on the result of the moves above
+                task(
+                    "/work/dl/src/only/deeper/main.rs",
+                    "/work/dl/src/only/main.rs"
+                ),
+            ]
+        );
+        assert_eq!(
+            plan.collapsed,
+            vec![
+                PathBuf::from("/work/a/a/a"),
+                PathBuf::from("/work/a/a"),
+                PathBuf::from("/work/dl/proj"),
+                PathBuf::from("/work/dl/src/only/deeper"),
+            ]
+        );
+        assert!(plan.skipped.is_empty());
+        assert_eq!(
+            fs.files(),
+            vec![
+                PathBuf::from("/work/a/file.txt"),
+                PathBuf::from("/work/dl/README"),
+                PathBuf::from("/work/dl/src/lib/x/y.rs"),
+                PathBuf::from("/work/dl/src/lib/z.rs"),
+                PathBuf::from("/work/dl/src/only/main.rs"),
+                PathBuf::from("/work/keep/one.txt"),
+                PathBuf::from("/work/keep/two.txt"),
+            ]
+        );
+    }
+
+    #[test]
+    fn collapses_the_working_dir_and_moved_wrappers() {
+        let fs = MemoryFs::new();
+        fs.add_file("/work/dl/movie/README", "");
+        fs.add_file("/work/dl/movie/x/y/movie.mkv", "");
+        fs.add_file("/work/dl/movie/x/y/movie.nfo", "");
+        let plan = plan(&fs, &[]);
+        assert_eq!(
+            plan.tasks,
+            vec![
+                task("/work/dl/movie/README", "/work/README"),
+                task("/work/dl/movie/x/y", "/work/x"),
+            ]
+        );
+        assert_eq!(
+            plan.collapsed,
+            vec![
+                PathBuf::from("/work/dl/movie/x"),
+                PathBuf::from("/work/dl/movie"),
+                PathBuf::from("/work/dl"),
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_ignored_entries_in_place() {
+        let fs = MemoryFs::new();
+        fs.add_file("/work/a/a/file.txt", "");
+        fs.add_file("/work/a/a/.cache/blob", "");
+        fs.add_file("/work/b/node_modules/x/index.js", "");
+        let plan = plan(&fs, &["/work/a/a/.cache", "/work/b/node_modules"]);
+        assert_eq!(
+            plan.tasks,
+            vec![task("/work/a/a/file.txt", "/work/a/file.txt")]
+        );
+        assert!(fs.exists(Path::new("/work/a/a/.cache/blob")));
+    }
+
+    #[test]
+    fn reports_clashes_of_later_rounds() {
+        let tree = || {
+            let fs = MemoryFs::new();
+            fs.add_file("/work/dl/proj/README", "");
+            fs.add_file("/work/dl/proj/src/lib.rs", "");
+            fs.add_file("/work/dl/proj/src/only/deeper/deeper", "inner");
+            fs.add_file("/work/dl/proj/src/only/deeper/other", "");
+            fs
+        };
+        let fs = tree();
+        let plan = plan(&fs, &[]);
+        let Some(CheckBeforeError::Target(files)) = &plan.clashes else {
+            panic!("the inner file should clash with its wrapper");
+        };
+        assert_eq!(
+            files,
+            &vec![PathBuf::from("/work/src/only/deeper/deeper")
+                .to_failed("would overwrite a file in the destination folder")]
+        );
+        assert!(plan.tasks.contains(&task(
+            "/work/src/only/deeper/deeper",
+            "/work/src/only/deeper"
+        )));
+        // nothing of the clashing round is made on the snapshot
+        assert_eq!(
+            fs.read_to_string(Path::new("/work/src/only/deeper/deeper"))
+                .unwrap(),
+            "inner"
+        );
+
+        let suffixed = WrapperPlan::new(
+            &tree(),
+            Path::new("/work"),
+            HashSet::new(),
+            ConflictPolicy::Suffix,
+            ClashRules::default(),
+        );
+        assert!(suffixed.clashes.is_none());
+    }
+}
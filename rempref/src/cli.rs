@@ -1,5 +1,8 @@
-use crate::logic::{Config, Rempref};
-use commons::file::traits::{InputArgs, Runnable};
+use commons::file::{
+    args::CommonArgs,
+    traits::{InputArgs, Runnable},
+};
+use rempref::{Config, Rempref};
 use std::path::PathBuf;
 use structopt::StructOpt;
 
@@ -8,8 +11,8 @@ use structopt::StructOpt;
 /// A file prefix removal utility
 pub struct Args {
     /// The length of the prefix to remove
-    #[structopt(short, long)]
-    pub prefix_length: u8,
+    #[structopt(short, long, required_unless = "undo")]
+    pub prefix_length: Option<u8>,
     /// Pass to make the file renames, otherwise it only does a dry run
     #[structopt(short, long)]
     pub do_renames: bool,
@@ -22,8 +25,8 @@ pub struct Args {
     /// Specify the working directory
     #[structopt(long)]
     pub working_dir: Option<PathBuf>,
-    // pub savepoint: bool, // save a csv with information to restore the renames
-    // pub load_savepoint: bool // restore the renames on failure
+    #[structopt(flatten)]
+    pub common: CommonArgs,
     // pub file_pattern: Option<Patterns>, // basic patterns enum with custom option
     // pub prefix_pattern: Option<String>, // eg remove prefixes that match this pattern
     // pub similarities: bool // search for similarities between files and create tasks based on those
@@ -37,11 +40,24 @@ impl InputArgs for Args {
     fn do_exec(&self) -> bool {
         self.do_renames
     }
+
+    fn common(&self) -> &CommonArgs {
+        &self.common
+    }
 }
 
 impl From<Args> for Config {
     fn from(args: Args) -> Self {
-        Config::new(args.prefix_length, args.extensions, args.recursive)
+        Config::new(
+            args.prefix_length.unwrap_or_default(),
+            args.extensions,
+            args.recursive,
+        )
+        .on_conflict(args.common.on_conflict)
+        .filter(args.common.path_filter())
+        .respect_ignore(!args.common.no_ignore)
+        .symlinks(args.common.symlinks)
+        .clash_rules(args.common.clash_rules())
     }
 }
 
@@ -0,0 +1,11 @@
+pub mod logic;
+
+pub use commons::file::{
+    clash::ClashRules,
+    conflict::ConflictPolicy,
+    executor::{Execution, Executor},
+    filter::PathFilter,
+    symlink::SymlinkPolicy,
+    traits::{FileOperation, Instantiate},
+};
+pub use logic::{Config, Rempref};
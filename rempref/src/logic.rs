@@ -1,17 +1,50 @@
 use commons::file::{
-    errors::CheckBeforeError,
-    is_hidden,
-    model::FileOperationTask,
+    clash::ClashRules,
+    conflict::{resolve_conflicts, ConflictPolicy},
+    errors::{CheckBeforeError, HookError, PathError},
+    filesystem::FileSystem,
+    filter::PathFilter,
+    model::{FailedFileOperation, FileOperationTask},
+    ordering::vacated_paths,
+    symlink::SymlinkPolicy,
     traits::{ExecuteTask, FileOperation, Instantiate, ToFailed, ToFileTask},
-    {filter_by_extension, read_files, walkdir},
+    transfer::move_path,
+    {filter_by_extension, read_files, read_files_ignoring, walkdir, without_hidden},
 };
-use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
-use std::{fs, io, path::PathBuf};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::{
+    collections::BTreeSet,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+const IGNORE_FILE: &str = ".remprefignore";
 
 pub struct Config {
     prefix_length: u8,
     extensions: Vec<String>,
     recursive: bool,
+    on_conflict: ConflictPolicy,
+    filter: PathFilter,
+    respect_ignore: bool,
+    symlinks: SymlinkPolicy,
+    clash_rules: ClashRules,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            prefix_length: 0,
+            extensions: vec![],
+            recursive: false,
+            on_conflict: ConflictPolicy::default(),
+            filter: PathFilter::default(),
+            respect_ignore: true,
+            symlinks: SymlinkPolicy::default(),
+            clash_rules: ClashRules::default(),
+        }
+    }
 }
 
 impl Config {
@@ -20,118 +53,205 @@ impl Config {
             prefix_length,
             extensions,
             recursive,
+            ..Self::default()
         }
     }
+
+    pub fn on_conflict(mut self, on_conflict: ConflictPolicy) -> Self {
+        self.on_conflict = on_conflict;
+        self
+    }
+
+    pub fn filter(mut self, filter: PathFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Skips the paths listed in .gitignore, .ignore and the tool's ignore file.
+    pub fn respect_ignore(mut self, respect_ignore: bool) -> Self {
+        self.respect_ignore = respect_ignore;
+        self
+    }
+
+    pub fn symlinks(mut self, symlinks: SymlinkPolicy) -> Self {
+        self.symlinks = symlinks;
+        self
+    }
+
+    pub fn clash_rules(mut self, clash_rules: ClashRules) -> Self {
+        self.clash_rules = clash_rules;
+        self
+    }
 }
 
 pub struct Rempref {
-    working_dir: PathBuf,
+    fs: Arc<dyn FileSystem>,
     tasks: Vec<FileOperationTask>,
+    skipped_tasks: Vec<FailedFileOperation>,
+    scan_errors: Vec<FailedFileOperation>,
     failed_tasks: Vec<(usize, io::Error)>,
+    clash_rules: ClashRules,
 }
 
 impl Instantiate<Config> for Rempref {
-    fn new(working_dir: PathBuf, config: Config) -> Self {
-        let files = if config.recursive {
-            read_files(&working_dir, None)
+    fn with_fs(fs: Arc<dyn FileSystem>, working_dir: PathBuf, config: Config) -> Self {
+        let depth = if config.recursive { None } else { Some(1) };
+        let (files, scan_errors) = if config.respect_ignore {
+            read_files_ignoring(
+                fs.as_ref(),
+                &working_dir,
+                depth,
+                IGNORE_FILE,
+                config.symlinks,
+            )
         } else {
-            read_files(&working_dir, Some(1))
+            read_files(fs.as_ref(), &working_dir, depth, config.symlinks)
         };
-        let filtered_files = filter_by_extension(files, &config.extensions)
-            .into_par_iter()
-            .filter(|file| !is_hidden(file))
-            .collect::<Vec<_>>();
+        let files = filter_by_extension(files, &config.extensions);
+        let (filtered_files, unreadable) =
+            without_hidden(fs.as_ref(), config.filter.apply(&working_dir, files));
         let mut rempref = Self {
-            working_dir,
+            fs,
             tasks: vec![],
+            skipped_tasks: vec![],
+            scan_errors: scan_errors.iter().map(FailedFileOperation::from).collect(),
             failed_tasks: vec![],
+            clash_rules: config.clash_rules,
         };
-        rempref.create_tasks(config.prefix_length, filtered_files);
+        rempref.create_tasks(config.prefix_length, filtered_files, config.on_conflict);
+        rempref.skipped_tasks.extend(unreadable);
         rempref
     }
 }
 
 impl Rempref {
-    fn create_tasks(&mut self, prefix_length: u8, files: Vec<PathBuf>) {
-        self.tasks = files.to_file_tasks(|from| {
-            let mut to = from.clone();
-            let filename = &from.file_name().unwrap().to_string_lossy()[prefix_length.into()..];
-            to.set_file_name(filename);
-            FileOperationTask::new(from, to)
+    fn create_tasks(
+        &mut self,
+        prefix_length: u8,
+        files: Vec<PathBuf>,
+        on_conflict: ConflictPolicy,
+    ) {
+        let mut unrenamable = vec![];
+        let files = files
+            .into_iter()
+            .filter_map(|from| match stripped_name(&from, prefix_length.into()) {
+                Ok(Some(name)) => Some((from, name)),
+                Ok(None) => {
+                    unrenamable.push(from.to_failed("name is not longer than the prefix"));
+                    None
+                }
+                Err(e) => {
+                    unrenamable.push(from.to_failed(&e.to_string()));
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        let tasks = files.to_file_tasks(|(from, name)| {
+            let to = from.with_file_name(name);
+            FileOperationTask::new(from, to).linked(self.fs.as_ref())
         });
+        (self.tasks, self.skipped_tasks) =
+            resolve_conflicts(self.fs.as_ref(), tasks, on_conflict, self.clash_rules);
+        self.skipped_tasks.extend(unrenamable);
     }
+
+    // renames stay in their folder, unless a destination was edited to go elsewhere
+    fn destination_dirs(&self) -> BTreeSet<PathBuf> {
+        self.tasks
+            .iter()
+            .filter_map(|task| task.to.parent())
+            .map(Path::to_path_buf)
+            .collect()
+    }
+}
+
+/// Cuts the first `prefix_length` characters off the file name, names that
+/// would end up empty have nothing left to keep.
+fn stripped_name(path: &Path, prefix_length: usize) -> Result<Option<String>, PathError> {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| PathError::InvalidUtf8(path.to_path_buf()))?;
+    Ok(name
+        .char_indices()
+        .nth(prefix_length)
+        .map(|(i, _)| name[i..].to_string()))
 }
 
 impl ExecuteTask for Rempref {
     fn check_before_execution(&self) -> Option<CheckBeforeError> {
-        let root_files = walkdir(&self.working_dir, Some(1));
-
-        let would_overwrite: Vec<_> = self
-            .tasks
-            .par_iter()
-            .flat_map(|task| {
-                let mut clashing_task_reason = vec![];
-
-                let task_clashes: Vec<_> = self
-                    .tasks
-                    .par_iter()
-                    .filter_map(|other_task| {
-                        if task != other_task || self.tasks.len() == 1 {
-                            let is_nested_clash =
-                                task.from != other_task.from && task.to == other_task.to;
-                            let is_outer_clash = root_files.contains(&task.to);
-                            let mut reasons = vec![];
-                            if is_nested_clash {
-                                reasons.push("would overwrite another renamed file");
-                            }
-                            if is_outer_clash {
-                                reasons.push("renaming would overwrite a file in root");
-                            }
-                            if !reasons.is_empty() {
-                                let fail = task.to_failed(&format!("\n- {}", reasons.join("\n- ")));
-                                return Some(fail);
-                            }
-                        }
-                        None
-                    })
-                    .collect();
-
-                clashing_task_reason.extend(task_clashes);
-
-                // Check if the task conflicts with root files
-                if root_files.contains(&task.to) {
-                    let root_file =
-                        task.to_failed("would be overwritten in root by the rename of a file");
-                    if !clashing_task_reason.contains(&root_file) {
-                        clashing_task_reason.push(root_file);
-                    }
+        let rules = self.clash_rules;
+        let destination_files = rules.index(
+            &self
+                .destination_dirs()
+                .iter()
+                .flat_map(|dir| walkdir(self.fs.as_ref(), dir, Some(1)))
+                .collect::<Vec<_>>(),
+        );
+        let vacated = rules.index(&vacated_paths(&self.tasks));
+        let (mut clashes, mut targets, mut symlinks) = (vec![], vec![], vec![]);
+        for task in self.tasks.iter().filter(|task| !task.overwrites()) {
+            let clashes_with_renamed = self.tasks.par_iter().any(|other_task| {
+                task.from != other_task.from && rules.clash(&task.to, &other_task.to)
+            });
+            if clashes_with_renamed {
+                clashes.push(task.to_failed("would overwrite another renamed file"));
+            }
+            if destination_files.contains(&task.to) && !vacated.contains(&task.to) {
+                let (failed, replaced) = if self.fs.is_symlink(&task.to) {
+                    (&mut symlinks, "a symlink")
+                } else {
+                    (&mut targets, "an existing file")
+                };
+                failed.push(task.to_failed(&format!("renaming would overwrite {replaced}")));
+                let existing_file = task
+                    .to
+                    .to_failed("would be overwritten by the rename of a file");
+                if !failed.contains(&existing_file) {
+                    failed.push(existing_file);
                 }
+            }
+        }
+        CheckBeforeError::first(clashes, targets, symlinks)
+    }
 
-                clashing_task_reason
-            })
-            .collect();
-
-        if !would_overwrite.is_empty() {
-            let mut would_overwrite_sorted = would_overwrite.clone();
-            would_overwrite_sorted.sort();
-            Some(CheckBeforeError::FilesWouldOverwrite(
-                would_overwrite_sorted,
-            ))
-        } else {
-            None
+    fn before_execute(&self) -> Result<bool, HookError> {
+        let mut created = false;
+        for dir in self.destination_dirs() {
+            if !self.fs.is_dir(&dir) {
+                self.fs.create_dir_all(&dir)?;
+                created = true;
+            }
         }
+        Ok(created)
     }
 
-    fn execute_task(task: &FileOperationTask) -> io::Result<()> {
-        fs::rename(&task.from, &task.to)
+    fn execute_task(&self, task: &FileOperationTask) -> io::Result<u64> {
+        move_path(self.fs.as_ref(), &task.from, &task.to)
     }
 }
 
 impl FileOperation<Config> for Rempref {
+    fn get_fs(&self) -> &dyn FileSystem {
+        self.fs.as_ref()
+    }
+
     fn get_tasks(&self) -> Vec<FileOperationTask> {
         self.tasks.clone()
     }
 
+    fn get_tasks_mut(&mut self) -> &mut Vec<FileOperationTask> {
+        &mut self.tasks
+    }
+
+    fn get_skipped_tasks(&self) -> Vec<FailedFileOperation> {
+        self.skipped_tasks.clone()
+    }
+
+    fn get_scan_errors(&self) -> Vec<FailedFileOperation> {
+        self.scan_errors.clone()
+    }
+
     fn get_failed_tasks(&self) -> &Vec<(usize, io::Error)> {
         &self.failed_tasks
     }
@@ -140,3 +260,256 @@ impl FileOperation<Config> for Rempref {
         &mut self.failed_tasks
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use commons::file::{
+        editor::{parse_plan, render_plan},
+        filesystem::MemoryFs,
+        progress::NoProgress,
+    };
+    use std::path::Path;
+
+    fn rempref(fs: &Arc<MemoryFs>, prefix_length: u8, recursive: bool) -> Rempref {
+        let config = Config::new(prefix_length, vec![], recursive);
+        Rempref::with_fs(fs.clone(), PathBuf::from("/work"), config)
+    }
+
+    fn task(from: &str, to: &str) -> FileOperationTask {
+        FileOperationTask::new(PathBuf::from(from), PathBuf::from(to))
+    }
+
+    #[test]
+    fn plans_prefix_removal() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/01_a.txt", "");
+        fs.add_file("/work/02_b.txt", "");
+        fs.add_file("/work/.01_hidden", "");
+        fs.add_file("/work/nested/03_c.txt", "");
+        assert_eq!(
+            rempref(&fs, 3, false).get_tasks(),
+            vec![
+                task("/work/01_a.txt", "/work/a.txt"),
+                task("/work/02_b.txt", "/work/b.txt"),
+            ]
+        );
+        assert_eq!(rempref(&fs, 3, true).get_tasks().len(), 3);
+    }
+
+    #[test]
+    fn skips_names_that_cannot_be_cut() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/äöü_umlaut.txt", "");
+        fs.add_file("/work/ab", "");
+        #[cfg(unix)]
+        {
+            use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+            fs.add_file(
+                Path::new("/work").join(OsStr::from_bytes(b"01_\xff.txt")),
+                "",
+            );
+        }
+        let rempref = rempref(&fs, 4, false);
+        assert_eq!(
+            rempref.get_tasks(),
+            vec![task("/work/äöü_umlaut.txt", "/work/umlaut.txt")]
+        );
+        let skipped = rempref.get_skipped_tasks();
+        assert!(skipped
+            .contains(&PathBuf::from("/work/ab").to_failed("name is not longer than the prefix")));
+        #[cfg(unix)]
+        assert!(skipped
+            .iter()
+            .any(|skipped| skipped.reason.contains("not valid UTF-8")));
+    }
+
+    #[test]
+    fn detects_clashes_with_renamed_and_existing_files() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/1_a.txt", "");
+        fs.add_file("/work/2_a.txt", "");
+        fs.add_file("/work/3_b.txt", "");
+        fs.add_dir("/work/b.txt");
+        let Some(CheckBeforeError::Clash(files)) = rempref(&fs, 2, false).check_before_execution()
+        else {
+            panic!("clashes should have been detected");
+        };
+        let paths = files
+            .iter()
+            .map(|file| file.file_path.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("/work/1_a.txt"),
+                PathBuf::from("/work/2_a.txt")
+            ]
+        );
+
+        fs.remove_file(Path::new("/work/2_a.txt")).unwrap();
+        let Some(CheckBeforeError::Target(files)) = rempref(&fs, 2, false).check_before_execution()
+        else {
+            panic!("the clash with the existing folder should have been detected");
+        };
+        assert!(files
+            .iter()
+            .any(|file| file.file_path == Path::new("/work/3_b.txt")));
+    }
+
+    #[test]
+    fn detects_clashes_by_case_and_normalization_when_asked() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/1_Photo.JPG", "");
+        fs.add_file("/work/2_photo.jpg", "");
+        fs.add_file("/work/3_cafe\u{301}.txt", "");
+        fs.add_dir("/work/caf\u{e9}.txt");
+        let with_rules = |rules: ClashRules| {
+            let config = Config::new(2, vec![], false).clash_rules(rules);
+            let rempref = Rempref::with_fs(fs.clone(), PathBuf::from("/work"), config);
+            match rempref.check_before_execution() {
+                Some(e) => e
+                    .files()
+                    .iter()
+                    .map(|file| file.file_path.clone())
+                    .collect::<BTreeSet<_>>(),
+                None => BTreeSet::new(),
+            }
+        };
+        assert!(with_rules(ClashRules::default()).is_empty());
+        assert_eq!(
+            with_rules(ClashRules::new(true, false)),
+            BTreeSet::from([
+                PathBuf::from("/work/1_Photo.JPG"),
+                PathBuf::from("/work/2_photo.jpg"),
+            ])
+        );
+        assert!(
+            with_rules(ClashRules::new(false, true)).contains(Path::new("/work/3_cafe\u{301}.txt"))
+        );
+    }
+
+    #[test]
+    fn suffixes_names_that_only_clash_by_case() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/1_Photo.JPG", "");
+        fs.add_file("/work/2_photo.jpg", "");
+        let config = Config::new(2, vec![], false)
+            .on_conflict(ConflictPolicy::Suffix)
+            .clash_rules(ClashRules::new(true, false));
+        let rempref = Rempref::with_fs(fs.clone(), PathBuf::from("/work"), config);
+        let destinations = rempref
+            .get_tasks()
+            .iter()
+            .map(|task| task.to.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            destinations,
+            vec![
+                PathBuf::from("/work/Photo.JPG"),
+                PathBuf::from("/work/photo (1).jpg")
+            ]
+        );
+        assert!(rempref.check_before_execution().is_none());
+    }
+
+    #[test]
+    fn checks_edited_destinations_in_other_folders() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/1_a.txt", "renamed");
+        fs.add_file("/work/2_b.txt", "renamed");
+        fs.add_file("/work/sub/a.txt", "existing");
+        let mut rempref = rempref(&fs, 2, false);
+        let working_dir = Path::new("/work");
+        let tasks = rempref.get_tasks();
+        let contents = render_plan(&tasks, working_dir)
+            .replace("1\ta.txt", "1\tsub/a.txt")
+            .replace("2\tb.txt", "2\tnew/b.txt");
+        *rempref.get_tasks_mut() = parse_plan(&tasks, working_dir, &contents).unwrap();
+        let files = rempref
+            .check_before_execution()
+            .expect("the clash in sub should have been detected")
+            .files()
+            .iter()
+            .map(|file| file.file_path.clone())
+            .collect::<BTreeSet<_>>();
+        assert_eq!(
+            files,
+            BTreeSet::from([
+                PathBuf::from("/work/1_a.txt"),
+                PathBuf::from("/work/sub/a.txt")
+            ])
+        );
+
+        *rempref.get_tasks_mut() = vec![task("/work/2_b.txt", "/work/new/b.txt")];
+        assert!(rempref.check_before_execution().is_none());
+        assert!(rempref.before_execute().unwrap());
+        assert_eq!(rempref.execute().failed, 0);
+        assert_eq!(
+            fs.read_to_string(Path::new("/work/new/b.txt")).unwrap(),
+            "renamed"
+        );
+    }
+
+    #[test]
+    fn renames_chains_in_a_safe_order() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/11a.txt", "first");
+        fs.add_file("/work/1a.txt", "second");
+        let mut rempref = rempref(&fs, 1, false);
+        assert!(rempref.check_before_execution().is_none());
+        let result = rempref.execute();
+        assert_eq!((result.successful, result.failed), (2, 0));
+        assert_eq!(
+            fs.read_to_string(Path::new("/work/1a.txt")).unwrap(),
+            "first"
+        );
+        assert_eq!(
+            fs.read_to_string(Path::new("/work/a.txt")).unwrap(),
+            "second"
+        );
+    }
+
+    #[test]
+    fn skips_tasks_depending_on_a_failed_rename() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/11a.txt", "first");
+        fs.add_file("/work/1a.txt", "second");
+        fs.fail_on("/work/a.txt");
+        let mut rempref = rempref(&fs, 1, false);
+        let result = rempref.execute();
+        assert_eq!((result.successful, result.failed), (0, 2));
+        assert_eq!(
+            fs.read_to_string(Path::new("/work/11a.txt")).unwrap(),
+            "first"
+        );
+        assert_eq!(
+            fs.read_to_string(Path::new("/work/1a.txt")).unwrap(),
+            "second"
+        );
+    }
+
+    #[test]
+    fn parallel_execution_keeps_dependent_renames_ordered() {
+        let fs = Arc::new(MemoryFs::new());
+        fs.add_file("/work/11a.txt", "first");
+        fs.add_file("/work/1a.txt", "second");
+        fs.add_file("/work/1b.txt", "third");
+        fs.add_file("/work/1c.txt", "fourth");
+        fs.fail_on("/work/1c.txt");
+        let mut rempref = rempref(&fs, 1, false);
+        let result = rempref.execute_with(4, &NoProgress);
+        assert_eq!((result.successful, result.failed), (3, 1));
+        assert_eq!(
+            fs.read_to_string(Path::new("/work/1a.txt")).unwrap(),
+            "first"
+        );
+        assert_eq!(
+            fs.read_to_string(Path::new("/work/a.txt")).unwrap(),
+            "second"
+        );
+        let failed = rempref.get_failed_operations();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].file_path, PathBuf::from("/work/1c.txt"));
+    }
+}